@@ -1,21 +1,90 @@
 package org.freedesktop.gstreamer;
 
+import java.io.ByteArrayOutputStream;
 import java.io.File;
+import java.io.FileNotFoundException;
 import java.io.FileOutputStream;
 import java.io.IOException;
 import java.io.InputStream;
 import java.io.OutputStream;
+import java.util.Collections;
+import java.util.List;
 
 import android.content.Context;
 import android.content.res.AssetManager;
 
+import org.json.JSONException;
+
 public class GStreamer {
-    private static native void nativeInit(Context context) throws Exception;
+    // Asset read by init when no plugin manifest was set.
+    private static final String PLUGIN_MANIFEST_ASSET = "gstreamer/plugins.txt";
+    private static String pluginManifest = null;
+
+    private static native void nativeInit(Context context, String pluginManifest) throws Exception;
+    private static native String nativeGetPluginReport();
+
+    // Plugins to register at init, one name per line, followed by "optional" if the app
+    // may lack it. Without a manifest, the gstreamer/plugins.txt asset is used if there
+    // is one, else the default plugins are registered. Must be called before init.
+    public static void setPluginManifest(String manifest) {
+        pluginManifest = manifest;
+    }
+
+    // Same as setPluginManifest, from lists of plugin names.
+    public static void setPlugins(String[] required, String[] optional) {
+        StringBuilder manifest = new StringBuilder();
+        for (String name : required) {
+            manifest.append(name).append('\n');
+        }
+        for (String name : optional) {
+            manifest.append(name).append(" optional\n");
+        }
+        setPluginManifest(manifest.toString());
+    }
+
+    // Outcome of registering each plugin of the manifest, empty before init. init fails
+    // when a required plugin is not loaded, this tells which ones and why.
+    public static List<PluginReport> getPluginReport() {
+        String report = nativeGetPluginReport();
+        if (report == null) {
+            return Collections.emptyList();
+        }
+        try {
+            return PluginReport.fromJsonLines(report);
+        } catch (JSONException e) {
+            throw new IllegalStateException("Invalid plugin report", e);
+        }
+    }
 
     public static void init(Context context) throws Exception {
         copyCaCertificates(context);
         copyFonts(context);
-        nativeInit(context);
+        String manifest = pluginManifest;
+        if (manifest == null) {
+            manifest = readAsset(context.getAssets(), PLUGIN_MANIFEST_ASSET);
+        }
+        nativeInit(context, manifest);
+    }
+
+    // Content of an UTF-8 asset, null if the app has no such asset.
+    private static String readAsset(AssetManager assetManager, String assetPath) throws IOException {
+        InputStream in;
+        try {
+            in = assetManager.open(assetPath);
+        } catch (FileNotFoundException e) {
+            return null;
+        }
+        try {
+            ByteArrayOutputStream out = new ByteArrayOutputStream();
+            byte[] buffer = new byte[1024];
+            int read;
+            while ((read = in.read(buffer)) != -1) {
+                out.write(buffer, 0, read);
+            }
+            return out.toString("UTF-8");
+        } finally {
+            in.close();
+        }
     }
 
     private static void copyFonts(Context context) {
@@ -0,0 +1,56 @@
+package org.freedesktop.gstreamer;
+
+import org.json.JSONException;
+import org.json.JSONObject;
+
+import java.util.ArrayList;
+import java.util.Collections;
+import java.util.List;
+import java.util.Locale;
+
+// How registering one plugin of the manifest went during GStreamer.init.
+public final class PluginReport {
+    public enum Status {
+        LOADED,
+        MISSING_LIBRARY,
+        MISSING_SYMBOL,
+        // The plugin's register function ran but GStreamer does not know the plugin.
+        REGISTRATION_FAILED,
+    }
+
+    public final String name;
+    public final boolean required;
+    public final Status status;
+    // Why the library or its register function could not be loaded, null otherwise.
+    public final String error;
+
+    private PluginReport(String name, boolean required, Status status, String error) {
+        this.name = name;
+        this.required = required;
+        this.status = status;
+        this.error = error;
+    }
+
+    @Override
+    public String toString() {
+        return name + (required ? "" : " (optional)") + ": " + status
+                + (error != null ? " (" + error + ")" : "");
+    }
+
+    // Parse the report of the native side, one JSON object per plugin and line.
+    static List<PluginReport> fromJsonLines(String lines) throws JSONException {
+        List<PluginReport> reports = new ArrayList<>();
+        for (String line : lines.split("\n")) {
+            if (line.isEmpty()) {
+                continue;
+            }
+            JSONObject json = new JSONObject(line);
+            reports.add(new PluginReport(
+                    json.getString("name"),
+                    json.getBoolean("required"),
+                    Status.valueOf(json.getString("status").toUpperCase(Locale.ROOT)),
+                    json.isNull("error") ? null : json.getString("error")));
+        }
+        return Collections.unmodifiableList(reports);
+    }
+}
@@ -6,6 +6,12 @@ import android.system.Os;
 import android.util.Log;
 
 import org.freedesktop.gstreamer.GStreamer;
+import org.freedesktop.gstreamer.PluginReport;
+import org.json.JSONException;
+
+import java.nio.ByteBuffer;
+import java.nio.ByteOrder;
+import java.util.List;
 
 public class AndroidSink {
     static {
@@ -13,20 +19,86 @@ public class AndroidSink {
         System.loadLibrary("androidsink");
     }
 
-    private static native void nativeRun();
+    // Notified when the pipeline cannot be created or fails while running. Runtime
+    // errors are reported from the pipeline's thread.
+    public interface ErrorListener {
+        // source: path of the failing element, or the missing element / invalid description fragment
+        void onError(String source, String message, String debug);
+    }
+
+    // Receives the measurements of the native analysers while the pipeline runs, in
+    // batches, on a worker thread.
+    public interface MetricsListener {
+        void onMetrics(List<Metric> metrics);
+    }
+
+    // Turns the JSON lines batches of the native side into Metric objects.
+    private static final class MetricsBridge {
+        private final MetricsListener listener;
+
+        MetricsBridge(MetricsListener listener) {
+            this.listener = listener;
+        }
+
+        // Called from native code.
+        void onBatch(String lines) {
+            try {
+                listener.onMetrics(Metric.fromJsonLines(lines));
+            } catch (JSONException e) {
+                Log.w(tag, "Cannot parse metrics", e);
+            }
+        }
+    }
+
+    // Receives the raw samples of every buffer the pipeline analyses, on a worker thread.
+    // Each buffer must be released once done with, see PcmBuffer.
+    public interface PcmListener {
+        void onPcm(PcmBuffer buffer);
+    }
+
+    // Wraps the native buffers of the native side into PcmBuffer objects.
+    private static final class PcmBridge {
+        private final PcmListener listener;
+
+        PcmBridge(PcmListener listener) {
+            this.listener = listener;
+        }
+
+        // Called from native code, data wraps the native buffer registered under token.
+        void onPcm(ByteBuffer data, long token, String format, int rate, int channels,
+                   long timestamp) {
+            ByteBuffer view = data.asReadOnlyBuffer().order(ByteOrder.nativeOrder());
+            listener.onPcm(new PcmBuffer(view, token, format, rate, channels, timestamp));
+        }
+    }
+
+    private static native long nativeCreate();
+    private static native void nativeDestroy(long handle);
+    private static native void nativeSetSampleRate(long handle, int sampleRate);
+    private static native void nativeSetBufSize(long handle, int bufSize);
+    private static native void nativeSetChannels(long handle, int channels);
+    private static native void nativeSetErrorListener(long handle, ErrorListener listener);
+    private static native void nativeSetMetricsListener(long handle, MetricsBridge bridge);
+    private static native void nativeSetPcmListener(long handle, PcmBridge bridge);
+    private static native void nativeReleasePcm(long token);
+    private static native void nativeSetRecording(long handle, String directory, boolean flac,
+                                                  long maxDurationMs, long maxSize);
+    private static native void nativeRun(long handle);
+    private static native void nativeStop(long handle);
+    private static native void nativePause(long handle);
+    private static native void nativeResume(long handle);
     private static AndroidSink INSTANCE = null;
+    private static boolean initialized = false;
     private static final String tag = "Androidsink";
 
-    private AndroidSink() {};
+    private long handle;
 
-    public static AndroidSink getInstance(Context context, int sampleRate, int bufSize) {
-        if (INSTANCE == null) {
-//            if (sampleRate != 0) {
-//                nativeSetSampleRate(sampleRate);
-//            }
-//            if (bufSize != 0) {
-//                nativeSetBufSize(bufSize);
-//            }
+    private AndroidSink(long handle) {
+        this.handle = handle;
+    }
+
+    private static boolean init(Context context) {
+        if (!initialized) {
             // Initialize GStreamer and warn if it fails
             try {
                 // Os.setenv("GST_DEBUG", "androidsink:7", true);
@@ -44,16 +116,91 @@ public class AndroidSink {
             try {
                 GStreamer.init(context);
             } catch (Exception e) {
+                for (PluginReport report : GStreamer.getPluginReport()) {
+                    Log.w(tag, report.toString());
+                }
                 Toast.makeText(context, e.getMessage(), Toast.LENGTH_LONG).show();
-                return null;
+                return false;
             }
-            INSTANCE = new AndroidSink();
+            initialized = true;
+        }
+        return true;
+    }
+
+    // Create an independent sink with its own pipeline.
+    public static synchronized AndroidSink create(Context context, int sampleRate, int bufSize) {
+        if (!init(context)) {
+            return null;
+        }
+        AndroidSink sink = new AndroidSink(nativeCreate());
+        if (sampleRate != 0) {
+            nativeSetSampleRate(sink.handle, sampleRate);
+        }
+        if (bufSize != 0) {
+            nativeSetBufSize(sink.handle, bufSize);
+        }
+        return sink;
+    }
+
+    public static synchronized AndroidSink getInstance(Context context, int sampleRate, int bufSize) {
+        if (INSTANCE == null) {
+            INSTANCE = create(context, sampleRate, bufSize);
         }
         return(INSTANCE);
     }
 
+    // Stop the pipeline and free the native session, the sink cannot be used afterwards.
+    public synchronized void release() {
+        if (handle != 0) {
+            nativeDestroy(handle);
+            handle = 0;
+        }
+    }
+
+    // Takes effect the next time the sink is started.
+    public void setChannels(int channels) {
+        nativeSetChannels(handle, channels);
+    }
+
+    // Record the analysed audio to WAV, or FLAC, files in an existing directory, starting
+    // a new file when one lasts maxDurationMs or reaches maxSize bytes (0 for no limit).
+    // A null directory stops recording. Takes effect the next time the sink is started.
+    public void setRecording(String directory, boolean flac, long maxDurationMs, long maxSize) {
+        nativeSetRecording(handle, directory, flac, maxDurationMs, maxSize);
+    }
+
+    public void setErrorListener(ErrorListener listener) {
+        nativeSetErrorListener(handle, listener);
+    }
+
+    // Takes effect the next time the sink is started, null stops the delivery.
+    public void setMetricsListener(MetricsListener listener) {
+        nativeSetMetricsListener(handle, listener == null ? null : new MetricsBridge(listener));
+    }
+
+    // Takes effect the next time the sink is started, null stops the delivery.
+    public void setPcmListener(PcmListener listener) {
+        nativeSetPcmListener(handle, listener == null ? null : new PcmBridge(listener));
+    }
+
+    static void releasePcm(long token) {
+        nativeReleasePcm(token);
+    }
+
     public void start() {
-        nativeRun();
+        nativeRun(handle);
+    }
+
+    public void stop() {
+        nativeStop(handle);
+    }
+
+    public void pause() {
+        nativePause(handle);
+    }
+
+    public void resume() {
+        nativeResume(handle);
     }
 
 }
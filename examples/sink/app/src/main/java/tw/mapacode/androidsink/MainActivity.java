@@ -2,9 +2,14 @@ package tw.mapacode.androidsink;
 
 import androidx.appcompat.app.AppCompatActivity;
 
+import android.content.Context;
+import android.media.AudioManager;
+import android.util.Log;
 import android.widget.Toast;
 import android.os.Bundle;
 
+import java.util.List;
+
 public class MainActivity extends AppCompatActivity {
 
     private static boolean gst_initialized = false;
@@ -14,9 +19,36 @@ public class MainActivity extends AppCompatActivity {
         super.onCreate(savedInstanceState);
         setContentView(R.layout.activity_main);
 
-        AndroidSink sink = AndroidSink.getInstance(this, 0,0);
+        // Use the audio HAL's preferred rate and buffer size when it reports them.
+        AudioManager audioManager = (AudioManager) getSystemService(Context.AUDIO_SERVICE);
+        int sampleRate = parseProperty(audioManager.getProperty(AudioManager.PROPERTY_OUTPUT_SAMPLE_RATE));
+        int bufSize = parseProperty(audioManager.getProperty(AudioManager.PROPERTY_OUTPUT_FRAMES_PER_BUFFER));
+
+        AndroidSink sink = AndroidSink.getInstance(this, sampleRate, bufSize);
         if (sink != null) {
+            sink.setErrorListener(new AndroidSink.ErrorListener() {
+                @Override
+                public void onError(String source, String message, String debug) {
+                    Log.e("Androidsink", source + ": " + message + " (debug: " + debug + ")");
+                }
+            });
+            sink.setMetricsListener(new AndroidSink.MetricsListener() {
+                @Override
+                public void onMetrics(List<Metric> metrics) {
+                    for (Metric metric : metrics) {
+                        Log.d("Androidsink", metric.toString());
+                    }
+                }
+            });
             sink.start();
         }
     }
-}
\ No newline at end of file
+
+    private static int parseProperty(String value) {
+        try {
+            return value == null ? 0 : Integer.parseInt(value);
+        } catch (NumberFormatException e) {
+            return 0;
+        }
+    }
+}
@@ -0,0 +1,81 @@
+package tw.mapacode.androidsink;
+
+import org.json.JSONException;
+import org.json.JSONObject;
+
+import java.util.ArrayList;
+import java.util.Collections;
+import java.util.Iterator;
+import java.util.LinkedHashMap;
+import java.util.List;
+import java.util.Map;
+
+// Measurement made by one of the native analysers, e.g. "level" or "pitch".
+public final class Metric {
+    // Time the measurement applies to in nanoseconds, on the timeline of the buffer
+    // timestamps, -1 when unknown.
+    public final long timestamp;
+    public final String streamId;
+    public final String name;
+    // Channel measured, null when the measurement covers all of them.
+    public final String channel;
+    // Values by name in the order the analyser gave them: Double, or String for text.
+    // Numbers not measured yet are NaN.
+    public final Map<String, Object> values;
+
+    private Metric(long timestamp, String streamId, String name, String channel,
+                   Map<String, Object> values) {
+        this.timestamp = timestamp;
+        this.streamId = streamId;
+        this.name = name;
+        this.channel = channel;
+        this.values = Collections.unmodifiableMap(values);
+    }
+
+    // Numeric value called name, NaN if there is none.
+    public double getNumber(String name) {
+        Object value = values.get(name);
+        return value instanceof Double ? (Double) value : Double.NaN;
+    }
+
+    @Override
+    public String toString() {
+        return name + (channel != null ? " " + channel : "") + ": " + values;
+    }
+
+    // Parse one line of JSON as written by the native metrics reporter.
+    static Metric fromJson(String line) throws JSONException {
+        JSONObject json = new JSONObject(line);
+        JSONObject jsonValues = json.getJSONObject("values");
+        Map<String, Object> values = new LinkedHashMap<>();
+        Iterator<String> names = jsonValues.keys();
+        while (names.hasNext()) {
+            String name = names.next();
+            Object value = jsonValues.get(name);
+            if (value instanceof Number) {
+                values.put(name, ((Number) value).doubleValue());
+            } else if (value == JSONObject.NULL) {
+                values.put(name, Double.NaN);
+            } else {
+                values.put(name, value.toString());
+            }
+        }
+        return new Metric(
+                json.isNull("timestamp") ? -1 : json.getLong("timestamp"),
+                json.getString("stream_id"),
+                json.getString("name"),
+                json.isNull("channel") ? null : json.getString("channel"),
+                values);
+    }
+
+    // Parse a batch of metrics, one JSON object per line.
+    static List<Metric> fromJsonLines(String lines) throws JSONException {
+        List<Metric> metrics = new ArrayList<>();
+        for (String line : lines.split("\n")) {
+            if (!line.isEmpty()) {
+                metrics.add(fromJson(line));
+            }
+        }
+        return metrics;
+    }
+}
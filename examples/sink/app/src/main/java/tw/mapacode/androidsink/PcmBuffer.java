@@ -0,0 +1,37 @@
+package tw.mapacode.androidsink;
+
+import java.nio.ByteBuffer;
+
+// Raw samples of one buffer of the pipeline, read in place from native memory.
+// The data must not be read after release(), and buffers which are never released
+// make the native side drop new ones once it holds too many.
+public final class PcmBuffer {
+    // Read-only, interleaved samples in native byte order.
+    public final ByteBuffer data;
+    // GStreamer audio format of the samples, e.g. "S16LE".
+    public final String format;
+    public final int rate;
+    public final int channels;
+    // Buffer timestamp (PTS) of the first sample in nanoseconds, -1 when unknown.
+    public final long timestamp;
+
+    private long token;
+
+    PcmBuffer(ByteBuffer data, long token, String format, int rate, int channels,
+              long timestamp) {
+        this.data = data;
+        this.token = token;
+        this.format = format;
+        this.rate = rate;
+        this.channels = channels;
+        this.timestamp = timestamp;
+    }
+
+    // Give the native memory back, releasing twice has no effect.
+    public synchronized void release() {
+        if (token != 0) {
+            AndroidSink.releasePcm(token);
+            token = 0;
+        }
+    }
+}
@@ -0,0 +1,191 @@
+use super::gstinit;
+use crate::{SampleConsumer, Samples, CAT};
+use anyhow::Error;
+use jni::objects::{GlobalRef, JObject};
+use jni::sys::{jlong, jobject};
+use jni::JNIEnv;
+use libc::c_void;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+// A mapped buffer along with the count of outstanding buffers of its forwarder, to
+// decrement on release.
+type HeldBuffer = (gst::MappedBuffer<gst::buffer::Readable>, Arc<AtomicUsize>);
+
+// Buffers handed to Java and not released yet, by token.
+static BUFFERS: Lazy<Mutex<HashMap<jlong, HeldBuffer>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+// Tokens start at 1 so that 0 can be used as "released" on the Java side.
+static NEXT_TOKEN: AtomicI64 = AtomicI64::new(1);
+
+// Buffers Java may hold at once per forwarder. Past that, buffers are not forwarded
+// so that a listener forgetting to release them cannot exhaust memory.
+const MAX_OUTSTANDING: usize = 64;
+
+// Buffer registered under `token`, waiting to be handed to Java.
+struct Pcm {
+    token: jlong,
+    data: *const u8,
+    size: usize,
+    format: &'static str,
+    rate: u32,
+    channels: u32,
+    pts: gst::ClockTime,
+}
+
+// The pointer stays valid until the token is released, which only Java does.
+unsafe impl Send for Pcm {}
+
+/// Hands the raw samples of every buffer to Java without copying them.
+///
+/// The mapped buffer is kept alive under a token until Java releases it, and is sent
+/// to a thread attached to the Java VM which wraps it in a direct `ByteBuffer`.
+pub struct PcmForwarder {
+    sender: Sender<Pcm>,
+    outstanding: Arc<AtomicUsize>,
+}
+
+impl PcmForwarder {
+    /// Create a forwarder calling `onPcm` on `bridge` from a new thread, which ends
+    /// once the forwarder is dropped.
+    pub fn new(bridge: GlobalRef) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || deliver(&bridge, receiver));
+        PcmForwarder {
+            sender,
+            outstanding: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+}
+
+impl SampleConsumer for PcmForwarder {
+    fn consume(&mut self, samples: &Samples) -> Result<(), Error> {
+        if self.outstanding.load(Ordering::Relaxed) >= MAX_OUTSTANDING {
+            gst_warning!(CAT, "too many PCM buffers held by Java, dropping one");
+            return Ok(());
+        }
+        let buffer = match samples.sample.get_buffer_owned() {
+            Some(buffer) => buffer,
+            None => return Ok(()),
+        };
+        let mapped = buffer
+            .into_mapped_buffer_readable()
+            .map_err(|_| anyhow::anyhow!("Failed to map buffer readable"))?;
+
+        let token = NEXT_TOKEN.fetch_add(1, Ordering::Relaxed);
+        let pcm = Pcm {
+            token,
+            data: mapped.as_slice().as_ptr(),
+            size: mapped.get_size(),
+            format: samples.info.format().to_str(),
+            rate: samples.info.rate(),
+            channels: samples.info.channels(),
+            pts: samples.pts,
+        };
+        self.outstanding.fetch_add(1, Ordering::Relaxed);
+        BUFFERS
+            .lock()
+            .unwrap()
+            .insert(token, (mapped, self.outstanding.clone()));
+        if self.sender.send(pcm).is_err() {
+            release(token);
+        }
+        Ok(())
+    }
+}
+
+/// Drop the buffer held for `token`, after which Java must not touch its data.
+pub fn release(token: jlong) {
+    match BUFFERS.lock().unwrap().remove(&token) {
+        Some((_, outstanding)) => {
+            outstanding.fetch_sub(1, Ordering::Relaxed);
+        }
+        None => gst_warning!(CAT, "no PCM buffer {}", token),
+    }
+}
+
+// Direct `ByteBuffer` over the `size` bytes at `data`, null with a Java exception
+// pending when the VM cannot make one. `JNIEnv::new_direct_byte_buffer` takes a
+// `&mut [u8]`, which must not be made over the memory of a readable mapping, so the
+// JNI function is called on the pointer itself.
+//
+// The caller must keep `data` valid as long as Java holds the buffer, and Java must
+// only read it.
+unsafe fn new_direct_byte_buffer(env: &JNIEnv, data: *const u8, size: usize) -> jobject {
+    let raw = env.get_native_interface();
+    match (**raw).NewDirectByteBuffer {
+        Some(new) => new(raw, data as *mut c_void, size as jlong),
+        None => std::ptr::null_mut(),
+    }
+}
+
+// Call `onPcm` on the bridge for every buffer received, until the channel closes.
+// Buffers which cannot be handed to Java are released right away.
+fn deliver(bridge: &GlobalRef, receiver: Receiver<Pcm>) {
+    let vm = match gstinit::java_vm() {
+        Some(vm) => vm,
+        None => {
+            gst_warning!(CAT, "no java vm to deliver PCM");
+            receiver.iter().for_each(|pcm| release(pcm.token));
+            return;
+        }
+    };
+    let env = match vm.attach_current_thread() {
+        Ok(env) => env,
+        Err(e) => {
+            gst_warning!(CAT, "could not attach thread: {}", e);
+            receiver.iter().for_each(|pcm| release(pcm.token));
+            return;
+        }
+    };
+
+    for pcm in receiver {
+        // SAFETY: the mapping stays alive under the token until Java releases it, and
+        // the bridge only hands `data.asReadOnlyBuffer()` to listeners, so the readable
+        // mapping is never written to.
+        let data = unsafe { new_direct_byte_buffer(&env, pcm.data, pcm.size) };
+        if data.is_null() {
+            gst_warning!(CAT, "could not wrap PCM in a direct ByteBuffer");
+            clear_exception(&env);
+            release(pcm.token);
+            continue;
+        }
+        let data = JObject::from(data);
+        let result = env.new_string(pcm.format).and_then(|format| {
+            let format = JObject::from(format);
+            let result = env.call_method(
+                bridge.as_obj(),
+                "onPcm",
+                "(Ljava/nio/ByteBuffer;JLjava/lang/String;IIJ)V",
+                &[
+                    data.into(),
+                    pcm.token.into(),
+                    format.into(),
+                    (pcm.rate as i32).into(),
+                    (pcm.channels as i32).into(),
+                    (pcm.pts.nseconds().map_or(-1, |pts| pts as i64)).into(),
+                ],
+            );
+            let _ = env.delete_local_ref(format);
+            result
+        });
+        // The thread never returns to Java, so local references must be freed here.
+        let _ = env.delete_local_ref(data);
+        if let Err(e) = result {
+            gst_warning!(CAT, "could not deliver PCM: {}", e);
+            clear_exception(&env);
+            release(pcm.token);
+        }
+    }
+    gst_debug!(CAT, "PCM delivery finished");
+}
+
+// Log and clear the pending Java exception, if any.
+fn clear_exception(env: &JNIEnv) {
+    if env.exception_check().unwrap_or(false) {
+        let _ = env.exception_describe();
+        let _ = env.exception_clear();
+    }
+}
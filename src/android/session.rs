@@ -0,0 +1,279 @@
+use super::gstinit;
+use super::pcm::PcmForwarder;
+use crate::level::LevelMeter;
+use crate::metrics::{self, Metric};
+use crate::record::RecordConfig;
+use crate::{Config, PipelineError, PipelineHandle, Rms, SampleConsumer, CAT, DEFAULT_DESCRIPTION};
+use jni::objects::{GlobalRef, JObject};
+use jni::sys::jlong;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+static SESSIONS: Lazy<Mutex<HashMap<jlong, Arc<Session>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+// Handles start at 1 so that 0 can be used as "no session" on the Java side.
+static NEXT_HANDLE: AtomicI64 = AtomicI64::new(1);
+
+// Metrics are handed to Java at most this often, or when this many have accumulated,
+// rather than one JNI call per metric.
+const METRICS_BATCH_INTERVAL: Duration = Duration::from_millis(100);
+const METRICS_BATCH_SIZE: usize = 256;
+
+/// Pipeline state of one `AndroidSink` instance.
+#[derive(Default)]
+pub struct Session {
+    // Applied the next time the pipeline is created.
+    config: Mutex<Config>,
+    // Set while the pipeline is running.
+    running: Mutex<Option<PipelineHandle>>,
+    // `AndroidSink.ErrorListener` notified when the pipeline fails.
+    error_listener: Mutex<Option<GlobalRef>>,
+    // `AndroidSink.MetricsBridge` receiving the metrics while the pipeline runs.
+    metrics_listener: Mutex<Option<GlobalRef>>,
+    // `AndroidSink.PcmBridge` receiving the raw samples while the pipeline runs.
+    pcm_listener: Mutex<Option<GlobalRef>>,
+}
+
+impl Session {
+    pub fn set_sample_rate(&self, sample_rate: Option<u32>) {
+        self.config.lock().unwrap().sample_rate = sample_rate;
+    }
+
+    pub fn set_samples_per_buffer(&self, samples_per_buffer: Option<u32>) {
+        self.config.lock().unwrap().samples_per_buffer = samples_per_buffer;
+    }
+
+    pub fn set_channels(&self, channels: Option<u32>) {
+        self.config.lock().unwrap().channels = channels;
+    }
+
+    pub fn set_record(&self, record: Option<RecordConfig>) {
+        self.config.lock().unwrap().record = record;
+    }
+
+    pub fn set_error_listener(&self, listener: Option<GlobalRef>) {
+        *self.error_listener.lock().unwrap() = listener;
+    }
+
+    pub fn set_metrics_listener(&self, listener: Option<GlobalRef>) {
+        *self.metrics_listener.lock().unwrap() = listener;
+    }
+
+    pub fn set_pcm_listener(&self, listener: Option<GlobalRef>) {
+        *self.pcm_listener.lock().unwrap() = listener;
+    }
+
+    fn report_error(&self, err: &PipelineError) {
+        gst_error!(CAT, "{}", err);
+        if let Some(listener) = self.error_listener.lock().unwrap().clone() {
+            notify_error(&listener, err);
+        }
+    }
+
+    /// Create the pipeline and run it on a new thread, unless it is already running.
+    pub fn run(self: &Arc<Self>) {
+        let mut running = self.running.lock().unwrap();
+        if running.is_some() {
+            gst_debug!(CAT, "session already running");
+            return;
+        }
+
+        let mut config = self.config.lock().unwrap().clone();
+        // With a listener the metrics go to Java instead of the log.
+        let metrics_listener = self.metrics_listener.lock().unwrap().clone();
+        let metrics = metrics_listener.map(|listener| {
+            let (reporter, receiver) = metrics::channel();
+            config.metrics = reporter;
+            (listener, receiver)
+        });
+        let mut consumers: Vec<Box<dyn SampleConsumer>> =
+            vec![Box::new(Rms), Box::new(LevelMeter::default())];
+        if let Some(listener) = self.pcm_listener.lock().unwrap().clone() {
+            consumers.push(Box::new(PcmForwarder::new(listener)));
+        }
+        let handle = match crate::create_pipeline(DEFAULT_DESCRIPTION, &config, consumers) {
+            Ok(pipeline) => PipelineHandle::new(pipeline),
+            Err(e) => {
+                self.report_error(&e.into());
+                return;
+            }
+        };
+        *running = Some(handle.clone());
+
+        // The channel closes when the pipeline is dropped, ending the thread.
+        if let Some((listener, receiver)) = metrics {
+            std::thread::spawn(move || deliver_metrics(&listener, receiver));
+        }
+
+        gst_trace!(CAT, "running");
+        let session = self.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = handle.run() {
+                session.report_error(&e);
+            }
+            session.running.lock().unwrap().take();
+            gst_trace!(CAT, "stopped running");
+        });
+    }
+
+    pub fn stop(&self) {
+        if let Some(handle) = self.running.lock().unwrap().as_ref() {
+            handle.stop();
+        }
+    }
+
+    pub fn pause(&self) {
+        if let Some(handle) = self.running.lock().unwrap().as_ref() {
+            if let Err(e) = handle.pause() {
+                gst_warning!(CAT, "could not pause: {}", e);
+            }
+        }
+    }
+
+    pub fn resume(&self) {
+        if let Some(handle) = self.running.lock().unwrap().as_ref() {
+            if let Err(e) = handle.resume() {
+                gst_warning!(CAT, "could not resume: {}", e);
+            }
+        }
+    }
+}
+
+// Split an error into the source, message and debug arguments of `onError`.
+fn error_fields(err: &PipelineError) -> (Option<String>, String, Option<String>) {
+    match err {
+        PipelineError::MissingElement(e) => (Some(e.0.clone()), err.to_string(), None),
+        PipelineError::InvalidDescription(e) => (Some(e.fragment.clone()), e.error.clone(), None),
+        PipelineError::Message(e) => (Some(e.src.clone()), e.error.clone(), e.debug.clone()),
+        PipelineError::Other(_) => (None, err.to_string(), None),
+    }
+}
+
+// Call `onError` on the listener, attaching the current thread to the Java VM if needed.
+fn notify_error(listener: &GlobalRef, err: &PipelineError) {
+    let vm = match gstinit::java_vm() {
+        Some(vm) => vm,
+        None => {
+            gst_warning!(CAT, "no java vm to notify error listener");
+            return;
+        }
+    };
+    let env = match vm.attach_current_thread() {
+        Ok(env) => env,
+        Err(e) => {
+            gst_warning!(CAT, "could not attach thread: {}", e);
+            return;
+        }
+    };
+
+    let (src, msg, debug) = error_fields(err);
+    let to_jstring = |s: Option<String>| match s {
+        Some(s) => env.new_string(s).map(JObject::from),
+        None => Ok(JObject::null()),
+    };
+    let result = to_jstring(src).and_then(|src| {
+        let msg = to_jstring(Some(msg))?;
+        let debug = to_jstring(debug)?;
+        env.call_method(
+            listener.as_obj(),
+            "onError",
+            "(Ljava/lang/String;Ljava/lang/String;Ljava/lang/String;)V",
+            &[src.into(), msg.into(), debug.into()],
+        )
+    });
+    if let Err(e) = result {
+        gst_warning!(CAT, "could not notify error listener: {}", e);
+        if env.exception_check().unwrap_or(false) {
+            let _ = env.exception_describe();
+            let _ = env.exception_clear();
+        }
+    }
+}
+
+// Call `onBatch` on the bridge with the metrics received, as JSON lines, until the
+// channel closes. The thread stays attached to the Java VM meanwhile.
+fn deliver_metrics(bridge: &GlobalRef, receiver: Receiver<Metric>) {
+    let vm = match gstinit::java_vm() {
+        Some(vm) => vm,
+        None => {
+            gst_warning!(CAT, "no java vm to deliver metrics");
+            return;
+        }
+    };
+    let env = match vm.attach_current_thread() {
+        Ok(env) => env,
+        Err(e) => {
+            gst_warning!(CAT, "could not attach thread: {}", e);
+            return;
+        }
+    };
+
+    while let Ok(metric) = receiver.recv() {
+        let mut batch = metric.to_json();
+        let deadline = Instant::now() + METRICS_BATCH_INTERVAL;
+        for _ in 1..METRICS_BATCH_SIZE {
+            match receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(metric) => {
+                    batch.push('\n');
+                    batch.push_str(&metric.to_json());
+                }
+                Err(_) => break,
+            }
+        }
+
+        let result = env.new_string(batch).and_then(|lines| {
+            let lines = JObject::from(lines);
+            let result = env.call_method(
+                bridge.as_obj(),
+                "onBatch",
+                "(Ljava/lang/String;)V",
+                &[lines.into()],
+            );
+            // The thread never returns to Java, so local references must be freed here.
+            let _ = env.delete_local_ref(lines);
+            result
+        });
+        if let Err(e) = result {
+            gst_warning!(CAT, "could not deliver metrics: {}", e);
+            if env.exception_check().unwrap_or(false) {
+                let _ = env.exception_describe();
+                let _ = env.exception_clear();
+            }
+        }
+    }
+    gst_debug!(CAT, "metrics delivery finished");
+}
+
+/// Register a new session and return its opaque handle.
+pub fn create() -> jlong {
+    let handle = NEXT_HANDLE.fetch_add(1, Ordering::Relaxed);
+    SESSIONS
+        .lock()
+        .unwrap()
+        .insert(handle, Arc::new(Session::default()));
+    gst_debug!(CAT, "created session {}", handle);
+    handle
+}
+
+/// Remove a session from the registry, stopping its pipeline if it is running.
+pub fn destroy(handle: jlong) {
+    match SESSIONS.lock().unwrap().remove(&handle) {
+        Some(session) => {
+            session.stop();
+            gst_debug!(CAT, "destroyed session {}", handle);
+        }
+        None => gst_warning!(CAT, "no session {}", handle),
+    }
+}
+
+pub fn get(handle: jlong) -> Option<Arc<Session>> {
+    let session = SESSIONS.lock().unwrap().get(&handle).cloned();
+    if session.is_none() {
+        gst_warning!(CAT, "no session {}", handle);
+    }
+    session
+}
@@ -0,0 +1,186 @@
+use std::io::Write;
+use std::process;
+
+use anyhow::{anyhow, bail, Error};
+use gst::prelude::*;
+
+use androidsink::clip::ClipDetector;
+use androidsink::distortion::DistortionAnalyser;
+use androidsink::dtmf::DtmfDecoder;
+use androidsink::level::LevelMeter;
+use androidsink::loudness::LoudnessMeter;
+use androidsink::metrics::{self, Metric};
+use androidsink::pitch::PitchDetector;
+use androidsink::record::{RecordConfig, RecordFormat};
+use androidsink::silence::SilenceDetector;
+use androidsink::spectrum::SpectrumAnalyser;
+use androidsink::vad::VoiceActivityDetector;
+use androidsink::{Config, PipelineHandle, Rms, SampleConsumer, DEFAULT_DESCRIPTION};
+
+const USAGE: &str = "\
+Usage: androidsink-cli [OPTIONS] [DESCRIPTION...]
+
+Analyse the audio produced by a gst-launch style DESCRIPTION, audiotestsrc by
+default, and print the metrics until the end of the stream or Ctrl-C.
+
+Options:
+  -a, --analyser NAME           Run an analyser, may be repeated (default: rms, level)
+                                rms, level, loudness, spectrum, pitch, vad, silence,
+                                clip, dtmf or distortion=FREQUENCY
+  -r, --rate HZ                 Sample rate to analyse at
+  -c, --channels N              Number of channels to analyse
+  -b, --samples-per-buffer N    Samples per buffer of the source
+  -j, --json                    Print the metrics as JSON lines
+      --record DIRECTORY        Also record the stream to WAV files in DIRECTORY
+  -h, --help                    Print this help";
+
+// Command line, as given by the user.
+#[derive(Debug, Default)]
+struct Options {
+    description: Vec<String>,
+    analysers: Vec<String>,
+    config: Config,
+    json: bool,
+}
+
+fn parse_number(option: &str, value: Option<String>) -> Result<u32, Error> {
+    let value = value.ok_or_else(|| anyhow!("{} needs a value", option))?;
+    value
+        .parse()
+        .map_err(|_| anyhow!("invalid value for {}: {}", option, value))
+}
+
+// Parse the arguments following the program name, `None` if help was asked for.
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Option<Options>, Error> {
+    let mut options = Options::default();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-a" | "--analyser" => match args.next() {
+                Some(name) => options.analysers.push(name),
+                None => bail!("{} needs a value", arg),
+            },
+            "-r" | "--rate" => options.config.sample_rate = Some(parse_number(&arg, args.next())?),
+            "-c" | "--channels" => options.config.channels = Some(parse_number(&arg, args.next())?),
+            "-b" | "--samples-per-buffer" => {
+                options.config.samples_per_buffer = Some(parse_number(&arg, args.next())?)
+            }
+            "-j" | "--json" => options.json = true,
+            "--record" => match args.next() {
+                Some(directory) => {
+                    options.config.record = Some(RecordConfig::new(directory, RecordFormat::Wav))
+                }
+                None => bail!("{} needs a value", arg),
+            },
+            "--" => options.description.extend(args.by_ref()),
+            _ if arg.starts_with('-') && options.description.is_empty() => {
+                bail!("unknown option {}", arg)
+            }
+            _ => options.description.push(arg),
+        }
+    }
+    if options.analysers.is_empty() {
+        options.analysers = vec![String::from("rms"), String::from("level")];
+    }
+    Ok(Some(options))
+}
+
+fn create_analyser(name: &str) -> Result<Box<dyn SampleConsumer>, Error> {
+    let analyser: Box<dyn SampleConsumer> = match name {
+        "rms" => Box::new(Rms),
+        "level" => Box::new(LevelMeter::default()),
+        "loudness" => Box::new(LoudnessMeter::default()),
+        "spectrum" => Box::new(SpectrumAnalyser::default()),
+        "pitch" => Box::new(PitchDetector::default()),
+        "vad" => Box::new(VoiceActivityDetector::default()),
+        "silence" => Box::new(SilenceDetector::default()),
+        "clip" => Box::new(ClipDetector::default()),
+        "dtmf" => Box::new(DtmfDecoder::default()),
+        _ => match name.strip_prefix("distortion=") {
+            Some(frequency) => match frequency.parse::<f64>() {
+                Ok(frequency) if frequency > 0.0 => Box::new(DistortionAnalyser::new(frequency)),
+                _ => bail!("invalid test tone frequency: {}", frequency),
+            },
+            None => bail!("unknown analyser {}", name),
+        },
+    };
+    Ok(analyser)
+}
+
+// Write every metric received to stdout until the pipeline is gone or stdout is closed.
+fn print_metrics(receiver: std::sync::mpsc::Receiver<Metric>, json: bool) {
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    for metric in receiver {
+        let result = if json {
+            writeln!(stdout, "{}", metric.to_json())
+        } else {
+            writeln!(stdout, "{} {}", metric.timestamp, metric)
+        };
+        if result.is_err() {
+            break;
+        }
+    }
+}
+
+fn run(options: Options) -> Result<(), Error> {
+    gst::init()?;
+
+    let consumers = options
+        .analysers
+        .iter()
+        .map(|name| create_analyser(name))
+        .collect::<Result<Vec<_>, _>>()?;
+    let description = if options.description.is_empty() {
+        String::from(DEFAULT_DESCRIPTION)
+    } else {
+        options.description.join(" ")
+    };
+
+    let mut config = options.config;
+    let (reporter, receiver) = metrics::channel();
+    config.metrics = reporter;
+    let pipeline = androidsink::create_pipeline(&description, &config, consumers)?;
+    // The channel closes once the pipeline, which holds the reporter, is dropped.
+    drop(config);
+    let json = options.json;
+    let printer = std::thread::spawn(move || print_metrics(receiver, json));
+
+    // Signal sources are dispatched by the default main context, which nothing else
+    // iterates in this program.
+    let weak = pipeline.downgrade();
+    glib::unix_signal_add(libc::SIGINT, move || {
+        if let Some(pipeline) = weak.upgrade() {
+            PipelineHandle::new(pipeline).stop();
+        }
+        glib::Continue(true)
+    });
+    let main_loop = glib::MainLoop::new(None, false);
+    std::thread::spawn({
+        let main_loop = main_loop.clone();
+        move || main_loop.run()
+    });
+
+    let result = PipelineHandle::new(pipeline).run();
+    main_loop.quit();
+    printer.join().unwrap();
+    result.map_err(Error::from)
+}
+
+fn main() {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(err) => {
+            eprintln!("androidsink-cli: {}\n\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(err) = run(options) {
+        eprintln!("androidsink-cli: {}", err);
+        process::exit(1);
+    }
+}
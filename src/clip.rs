@@ -0,0 +1,296 @@
+use anyhow::Error;
+
+use crate::consumer::FrameAccumulator;
+use crate::{SampleConsumer, Samples};
+
+/// Name of the element message posted for every clip, with the `channel`, the `time`
+/// the clip started, its `duration`, its length in `samples` and the channel's running
+/// clip `ratio`.
+pub const CLIP_MESSAGE: &str = "androidsink-clip";
+
+/// Run of consecutive full-scale samples on one channel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClipEvent {
+    pub channel: usize,
+    /// Buffer timestamp of the first clipped sample.
+    pub start: gst::ClockTime,
+    pub duration: gst::ClockTime,
+    /// Number of clipped samples.
+    pub samples: u64,
+    /// Clip ratio of the channel so far, this clip included.
+    pub ratio: f64,
+}
+
+impl ClipEvent {
+    /// Structure of the element message reporting the clip.
+    pub fn to_structure(&self) -> gst::Structure {
+        gst::Structure::builder(CLIP_MESSAGE)
+            .field("channel", &(self.channel as u32))
+            .field("time", &self.start)
+            .field("duration", &self.duration)
+            .field("samples", &self.samples)
+            .field("ratio", &self.ratio)
+            .build()
+    }
+}
+
+/// Absolute value from which samples of `format`, normalised with full scale at 1.0,
+/// are full scale: one step of the format below it, which is the highest positive
+/// value of integer formats. Float samples use the 16 bit step, as they often come
+/// from 16 bit sources.
+pub fn full_scale_threshold(format: gst_audio::AudioFormat) -> f32 {
+    let bits = match format {
+        gst_audio::AUDIO_FORMAT_U8 => 8,
+        gst_audio::AUDIO_FORMAT_S32 => 32,
+        _ => 16,
+    };
+    (1.0 - 0.5f64.powi(bits - 1)) as f32
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct ChannelState {
+    // Start time and length of the current run of full-scale samples.
+    run_start: u64,
+    run: u64,
+    clipped: u64,
+    total: u64,
+}
+
+impl ChannelState {
+    // Clip of `channel` ending the current run, if it lasted `min_run` samples.
+    fn end_run(
+        &mut self,
+        channel: usize,
+        min_run: u64,
+        frames: &FrameAccumulator,
+    ) -> Option<ClipEvent> {
+        let run = std::mem::take(&mut self.run);
+        if run < min_run {
+            return None;
+        }
+        self.clipped += run;
+        Some(ClipEvent {
+            channel,
+            start: gst::ClockTime::from_nseconds(self.run_start),
+            duration: gst::ClockTime::from_nseconds(frames.frames_to_ns(run as usize)),
+            samples: run,
+            ratio: self.clipped as f64 / self.total as f64,
+        })
+    }
+}
+
+/// Counts consecutive full-scale samples per channel and reports runs of at least
+/// `min_run` samples as clips.
+#[derive(Debug)]
+pub struct ClipDetector {
+    threshold: f32,
+    // Whether `threshold` follows the format of the analysed buffers.
+    from_format: bool,
+    min_run: u64,
+    frames: FrameAccumulator,
+    channels: Vec<ChannelState>,
+}
+
+impl Default for ClipDetector {
+    /// Samples within one step of full scale in the negotiated format (see
+    /// `full_scale_threshold`), 3 in a row.
+    fn default() -> Self {
+        ClipDetector {
+            from_format: true,
+            ..ClipDetector::new(full_scale_threshold(gst_audio::AUDIO_FORMAT_F32), 3)
+        }
+    }
+}
+
+impl ClipDetector {
+    /// Create a detector treating samples whose absolute value reaches `threshold`
+    /// (full scale being 1.0) as full scale, whatever the format.
+    pub fn new(threshold: f32, min_run: u64) -> Self {
+        ClipDetector {
+            threshold,
+            from_format: false,
+            min_run: min_run.max(1),
+            frames: FrameAccumulator::default(),
+            channels: Vec::new(),
+        }
+    }
+
+    /// Share of the samples of `channel` seen so far which were part of a clip.
+    pub fn clip_ratio(&self, channel: usize) -> f64 {
+        match self.channels.get(channel) {
+            Some(state) if state.total > 0 => state.clipped as f64 / state.total as f64,
+            _ => 0.0,
+        }
+    }
+
+    /// Check a buffer of interleaved `samples` with `channels` channels at `rate` Hz
+    /// starting at timestamp `pts`, and return the clips which ended in it.
+    pub fn process(
+        &mut self,
+        samples: &[f32],
+        channels: usize,
+        rate: u32,
+        pts: gst::ClockTime,
+    ) -> Vec<ClipEvent> {
+        if self.channels.len() != channels {
+            self.channels = vec![ChannelState::default(); channels];
+        }
+        self.frames.start_buffer(rate, pts);
+
+        let mut events = Vec::new();
+        let mut frames = 0;
+        for (frame, values) in samples.chunks_exact(channels).enumerate() {
+            let time = self.frames.time(frame);
+            for (channel, (sample, state)) in
+                values.iter().zip(self.channels.iter_mut()).enumerate()
+            {
+                state.total += 1;
+                if sample.abs() >= self.threshold {
+                    if state.run == 0 {
+                        state.run_start = time;
+                    }
+                    state.run += 1;
+                    continue;
+                }
+
+                events.extend(state.end_run(channel, self.min_run, &self.frames));
+            }
+            frames += 1;
+        }
+
+        self.frames.advance(frames);
+        events
+    }
+
+    /// Clips still going on at the end of the stream, ending them there.
+    pub fn finish(&mut self) -> Vec<ClipEvent> {
+        let (min_run, frames) = (self.min_run, &self.frames);
+        self.channels
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(channel, state)| state.end_run(channel, min_run, frames))
+            .collect()
+    }
+
+    // Report `events` as metrics and element messages.
+    fn report(samples: &Samples, events: Vec<ClipEvent>) -> Result<(), Error> {
+        for event in events {
+            samples.report(
+                samples
+                    .metric("clip")
+                    .with_timestamp(event.start)
+                    .with_channel(samples.channel_name(event.channel))
+                    .with_value("samples", event.samples)
+                    .with_value("duration", event.duration)
+                    .with_value("ratio", event.ratio),
+            );
+            samples.post(event.to_structure())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl SampleConsumer for ClipDetector {
+    fn consume(&mut self, samples: &Samples) -> Result<(), Error> {
+        if self.from_format {
+            self.threshold = full_scale_threshold(samples.info.format());
+        }
+        let events = self.process(
+            samples.data,
+            samples.channels(),
+            samples.info.rate(),
+            samples.pts,
+        );
+        ClipDetector::report(samples, events)
+    }
+
+    fn end_of_stream(&mut self, samples: &Samples) -> Result<(), Error> {
+        let events = self.finish();
+        ClipDetector::report(samples, events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clip_reported() {
+        // Stereo at 1 kHz, the right channel full scale for 5 frames from frame 10.
+        let mut samples = vec![0.5; 2 * 40];
+        for frame in 10..15 {
+            samples[2 * frame + 1] = -1.0;
+        }
+        // One full scale sample is not a clip.
+        samples[2 * 30] = 1.0;
+
+        let mut detector = ClipDetector::default();
+        let pts = gst::ClockTime::from_seconds(1);
+        let events = detector.process(&samples, 2, 1000, pts);
+        assert_eq!(events.len(), 1);
+        let clip = events[0];
+        assert_eq!(clip.channel, 1);
+        assert_eq!(clip.start, gst::ClockTime::from_mseconds(1010));
+        assert_eq!(clip.duration, gst::ClockTime::from_mseconds(5));
+        assert_eq!(clip.samples, 5);
+        // Of the 16 samples seen when the clip ended.
+        assert_eq!(clip.ratio, 5.0 / 16.0);
+        assert_eq!(detector.clip_ratio(0), 0.0);
+    }
+
+    #[test]
+    fn clip_across_buffers() {
+        let mut detector = ClipDetector::default();
+        let mut buffer = vec![0.0; 10];
+        buffer[7..].iter_mut().for_each(|sample| *sample = 1.0);
+        assert_eq!(
+            detector.process(&buffer, 1, 1000, gst::ClockTime::from_mseconds(0)),
+            []
+        );
+
+        let events = detector.process(&[1.0, 1.0, 0.0], 1, 1000, gst::ClockTime::none());
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].start, gst::ClockTime::from_mseconds(7));
+        assert_eq!(events[0].samples, 5);
+    }
+
+    #[test]
+    fn full_scale_of_formats() {
+        let threshold = full_scale_threshold;
+        assert_eq!(threshold(gst_audio::AUDIO_FORMAT_U8), 127.0 / 128.0);
+        assert_eq!(threshold(gst_audio::AUDIO_FORMAT_S16), 32_767.0 / 32_768.0);
+        assert_eq!(threshold(gst_audio::AUDIO_FORMAT_F32), 32_767.0 / 32_768.0);
+        assert_eq!(threshold(gst_audio::AUDIO_FORMAT_S32), 1.0);
+
+        // The highest positive U8 sample, normalised.
+        let mut detector = ClipDetector::new(threshold(gst_audio::AUDIO_FORMAT_U8), 3);
+        let events = detector.process(
+            &[127.0 / 128.0, 0.992, 0.992, 0.0],
+            1,
+            1000,
+            gst::ClockTime::none(),
+        );
+        assert!(events.is_empty());
+        let events = detector.process(&[127.0 / 128.0; 3], 1, 1000, gst::ClockTime::none());
+        assert!(events.is_empty());
+        assert_eq!(detector.finish().len(), 1);
+    }
+
+    #[test]
+    fn clip_open_at_end() {
+        let mut detector = ClipDetector::default();
+        let buffer = [0.0, 0.0, 1.0, -1.0, 1.0, 1.0];
+        assert_eq!(
+            detector.process(&buffer, 1, 1000, gst::ClockTime::from_seconds(1)),
+            []
+        );
+        let events = detector.finish();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].start, gst::ClockTime::from_mseconds(1002));
+        assert_eq!(events[0].duration, gst::ClockTime::from_mseconds(4));
+        assert_eq!(events[0].samples, 4);
+        assert_eq!(events[0].ratio, 4.0 / 6.0);
+        assert_eq!(detector.finish(), []);
+    }
+}
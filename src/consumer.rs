@@ -0,0 +1,228 @@
+use gst::prelude::*;
+#[cfg(test)]
+use std::sync::{Arc, Mutex};
+
+use anyhow::Error;
+
+use crate::convert;
+use crate::metrics::{Metric, Reporter};
+
+/// Samples of one buffer pulled from the analysis appsink.
+pub struct Samples<'a> {
+    /// Interleaved samples of the buffer, normalised to f32 with full scale at 1.0
+    /// whatever the negotiated format.
+    pub data: &'a [f32],
+    /// Audio format negotiated on the appsink.
+    pub info: &'a gst_audio::AudioInfo,
+    /// The sample pulled from the appsink, holding the buffer in the negotiated format.
+    pub sample: &'a gst::Sample,
+    /// Presentation timestamp of the buffer.
+    pub pts: gst::ClockTime,
+    /// Duration of the buffer.
+    pub duration: gst::ClockTime,
+    /// The analysis appsink, to post messages from.
+    pub sink: &'a gst::Element,
+    /// Stream id of the analysed stream, empty if the source did not set one.
+    pub stream_id: &'a str,
+    /// Where the metrics measured on the buffer go.
+    pub reporter: &'a Reporter,
+}
+
+impl<'a> Samples<'a> {
+    pub fn channels(&self) -> usize {
+        self.info.channels() as usize
+    }
+
+    /// Samples of one channel, taken from the interleaved data.
+    pub fn channel(&self, index: usize) -> impl Iterator<Item = f32> + 'a {
+        self.data
+            .iter()
+            .skip(index)
+            .step_by(self.channels())
+            .copied()
+    }
+
+    /// Name of a channel, from its negotiated position if the layout is positioned or
+    /// its index otherwise.
+    pub fn channel_name(&self, index: usize) -> String {
+        match self.info.positions().and_then(|p| p.get(index)) {
+            Some(position) => format!("{:?}", position),
+            None => index.to_string(),
+        }
+    }
+
+    /// Metric named `name` timestamped with the buffer, to which values are added
+    /// before passing it to `report`.
+    pub fn metric(&self, name: &str) -> Metric {
+        Metric::new(self.pts, self.stream_id, name)
+    }
+
+    pub fn report(&self, metric: Metric) {
+        self.reporter.report(metric);
+    }
+
+    /// Post an element message with `structure` on the pipeline's bus.
+    pub fn post(&self, structure: gst::Structure) -> Result<(), Error> {
+        let msg = gst::message::Element::builder(structure)
+            .src(self.sink)
+            .build();
+        self.sink.post_message(msg)?;
+        Ok(())
+    }
+}
+
+// Timestamps of the frames of consecutive buffers, along with the frames mixed down to
+// mono which analysers working on blocks of a fixed size keep from one buffer to the
+// next. A buffer without a timestamp follows on from the previous one.
+#[derive(Debug, Default)]
+pub(crate) struct FrameAccumulator {
+    rate: u32,
+    pending: Vec<f64>,
+    // Timestamp of `pending[0]`, or of the next frame when none is pending, in
+    // nanoseconds.
+    position: u64,
+}
+
+impl FrameAccumulator {
+    // Start a buffer of frames at `rate` Hz with timestamp `pts`. The frames pending at
+    // another rate are dropped, in which case `true` is returned.
+    pub fn start_buffer(&mut self, rate: u32, pts: gst::ClockTime) -> bool {
+        let rate_changed = rate != self.rate;
+        if rate_changed {
+            self.rate = rate;
+            self.pending.clear();
+        }
+        if let Some(pts) = pts.nseconds() {
+            self.position = pts.saturating_sub(self.frames_to_ns(self.pending.len()));
+        }
+        rate_changed
+    }
+
+    // Keep interleaved `samples` with `channels` channels, mixed down to mono.
+    pub fn push(&mut self, samples: &[f32], channels: usize) {
+        self.pending.extend(convert::mixdown(samples, channels));
+    }
+
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    // Timestamp of the frame `frames` after the current position.
+    pub fn time(&self, frames: usize) -> u64 {
+        self.position + self.frames_to_ns(frames)
+    }
+
+    pub fn frames_to_ns(&self, frames: usize) -> u64 {
+        frames as u64 * 1_000_000_000 / u64::from(self.rate)
+    }
+
+    // The first `size` pending frames, once that many are pending.
+    pub fn block(&self, size: usize) -> Option<&[f64]> {
+        self.pending.get(..size)
+    }
+
+    // Move on by `frames`, dropping them from the pending frames.
+    pub fn advance(&mut self, frames: usize) {
+        self.pending.drain(..frames.min(self.pending.len()));
+        self.position = self.time(frames);
+    }
+}
+
+/// Analyser fed with every buffer reaching the analysis appsink.
+///
+/// Consumers run on the streaming thread in the order they were registered. An error
+/// returned from `consume` is posted on the bus as an element error and stops the
+/// pipeline.
+pub trait SampleConsumer: Send {
+    fn consume(&mut self, samples: &Samples) -> Result<(), Error>;
+
+    /// Called at the end of the stream, to report what is still in progress. `samples`
+    /// holds no data nor timestamps, only the format and sample of the last buffer.
+    fn end_of_stream(&mut self, _samples: &Samples) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+// Consumer handing every buffer to an analysis and keeping what it returns, for tests
+// to check once the pipeline ran.
+#[cfg(test)]
+pub(crate) struct Collector<T, F> {
+    analyse: F,
+    collected: Arc<Mutex<Vec<T>>>,
+}
+
+#[cfg(test)]
+impl<T, F> Collector<T, F>
+where
+    T: Send,
+    F: FnMut(&Samples) -> Vec<T> + Send,
+{
+    // The collector and what it will collect.
+    pub fn new(analyse: F) -> (Self, Arc<Mutex<Vec<T>>>) {
+        let collected = Arc::new(Mutex::new(Vec::new()));
+        let collector = Collector {
+            analyse,
+            collected: collected.clone(),
+        };
+        (collector, collected)
+    }
+}
+
+#[cfg(test)]
+impl<T, F> SampleConsumer for Collector<T, F>
+where
+    T: Send,
+    F: FnMut(&Samples) -> Vec<T> + Send,
+{
+    fn consume(&mut self, samples: &Samples) -> Result<(), Error> {
+        let results = (self.analyse)(samples);
+        self.collected.lock().unwrap().extend(results);
+        Ok(())
+    }
+}
+
+// `len` samples of uniform white noise, at about -11 dBFS, from a linear congruential
+// generator so that tests see the same noise on every run.
+#[cfg(test)]
+pub(crate) fn white_noise(len: usize) -> Vec<f32> {
+    let mut state = 12345u32;
+    (0..len)
+        .map(|_| {
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (f64::from(state) / f64::from(u32::MAX) - 0.5) as f32
+        })
+        .collect()
+}
+
+/// Reports the root mean square of every buffer, per channel.
+#[derive(Debug, Default)]
+pub struct Rms;
+
+impl SampleConsumer for Rms {
+    fn consume(&mut self, samples: &Samples) -> Result<(), Error> {
+        for channel in 0..samples.channels() {
+            // For buffer (= chunk of samples), we calculate the root mean square:
+            // (https://en.wikipedia.org/wiki/Root_mean_square)
+            let (sum, count) =
+                samples
+                    .channel(channel)
+                    .fold((0f64, 0usize), |(sum, count), sample| {
+                        let f = f64::from(sample);
+                        (sum + f * f, count + 1)
+                    });
+            let rms = (sum / (count as f64)).sqrt();
+            samples.report(
+                samples
+                    .metric("rms")
+                    .with_channel(samples.channel_name(channel))
+                    .with_value("rms", rms),
+            );
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,57 @@
+use byte_slice_cast::*;
+
+use anyhow::{anyhow, Error};
+
+/// Sample formats accepted by the analysis appsink, in order of preference.
+pub const FORMATS: [gst_audio::AudioFormat; 4] = [
+    gst_audio::AUDIO_FORMAT_F32,
+    gst_audio::AUDIO_FORMAT_S32,
+    gst_audio::AUDIO_FORMAT_S16,
+    gst_audio::AUDIO_FORMAT_U8,
+];
+
+// Caps field value listing `FORMATS`.
+pub(crate) fn formats_list() -> gst::List<'static> {
+    let names: Vec<&str> = FORMATS.iter().map(|format| format.to_str()).collect();
+    let values: Vec<&dyn glib::ToSendValue> = names
+        .iter()
+        .map(|name| name as &dyn glib::ToSendValue)
+        .collect();
+    gst::List::new(&values)
+}
+
+// Interpret `data` as samples of `format` and store them in `out`, normalised to f32
+// with full scale at 1.0.
+pub(crate) fn normalize(
+    format: gst_audio::AudioFormat,
+    data: &[u8],
+    out: &mut Vec<f32>,
+) -> Result<(), Error> {
+    out.clear();
+    match format {
+        gst_audio::AUDIO_FORMAT_F32 => out.extend_from_slice(data.as_slice_of::<f32>()?),
+        gst_audio::AUDIO_FORMAT_S32 => out.extend(
+            data.as_slice_of::<i32>()?
+                .iter()
+                .map(|sample| (f64::from(*sample) / 2_147_483_648.0) as f32),
+        ),
+        gst_audio::AUDIO_FORMAT_S16 => out.extend(
+            data.as_slice_of::<i16>()?
+                .iter()
+                .map(|sample| f32::from(*sample) / 32_768.0),
+        ),
+        gst_audio::AUDIO_FORMAT_U8 => out.extend(
+            data.iter()
+                .map(|sample| (f32::from(*sample) - 128.0) / 128.0),
+        ),
+        _ => return Err(anyhow!("Unsupported sample format {}", format.to_str())),
+    }
+    Ok(())
+}
+
+// Mix interleaved samples with `channels` channels down to mono.
+pub(crate) fn mixdown(samples: &[f32], channels: usize) -> impl Iterator<Item = f64> + '_ {
+    samples
+        .chunks_exact(channels)
+        .map(move |frame| frame.iter().map(|s| f64::from(*s)).sum::<f64>() / channels as f64)
+}
@@ -0,0 +1,272 @@
+use anyhow::Error;
+
+use crate::consumer::FrameAccumulator;
+use crate::fft;
+use crate::spectrum::Window;
+use crate::{SampleConsumer, Samples};
+
+// Bins on each side of a peak holding the main lobe of the Blackman-Harris window.
+const LOBE: usize = 5;
+
+// Highest harmonic included in the THD.
+const MAX_HARMONIC: usize = 10;
+
+// How far the fundamental must stand above everything else, in dB, for the tone to
+// be considered present.
+const MIN_TONE_DB: f64 = 10.0;
+
+/// Quality of a test tone measured on one frame.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Distortion {
+    /// Buffer timestamp at which the frame starts.
+    pub start: gst::ClockTime,
+    /// Measured frequency of the fundamental in Hz.
+    pub frequency: f64,
+    /// RMS level of the fundamental in dBFS.
+    pub level_db: f64,
+    /// Total harmonic distortion: RMS of the harmonics over that of the fundamental.
+    pub thd: f64,
+    /// Total harmonic distortion plus noise: RMS of everything but the fundamental
+    /// over that of the fundamental.
+    pub thd_n: f64,
+    /// Ratio of the fundamental to everything but it and its harmonics, in dB.
+    pub snr_db: f64,
+}
+
+fn ratio_to_db(ratio: f64) -> f64 {
+    20.0 * ratio.log10()
+}
+
+/// Measures THD, THD+N and SNR of a sine of known frequency, such as one played by
+/// `audiotestsrc`, over the channels mixed down to mono. DC is left out of every
+/// measurement.
+#[derive(Debug)]
+pub struct DistortionAnalyser {
+    frequency: f64,
+    size: usize,
+    window: Vec<f64>,
+    window_power: f64,
+    frames: FrameAccumulator,
+}
+
+impl DistortionAnalyser {
+    /// Analyse frames of 16384 samples for a tone of `frequency` Hz.
+    pub fn new(frequency: f64) -> Self {
+        DistortionAnalyser::with_size(frequency, 16384)
+    }
+
+    /// Analyse frames of `size` samples, a power of two, for a tone of `frequency` Hz.
+    /// Longer frames separate the tone from the noise floor better.
+    pub fn with_size(frequency: f64, size: usize) -> Self {
+        assert!(size.is_power_of_two(), "FFT size must be a power of two");
+
+        let window = Window::BlackmanHarris.coefficients(size);
+        DistortionAnalyser {
+            frequency,
+            size,
+            window_power: window.iter().map(|w| w * w).sum(),
+            window,
+            frames: FrameAccumulator::default(),
+        }
+    }
+
+    /// Add interleaved `samples` with `channels` channels at `rate` Hz starting at
+    /// timestamp `pts`, and return the measurements of every frame completed by them.
+    /// Frames in which the tone is not found, or too low for its harmonics to be told
+    /// apart from it, are skipped.
+    pub fn process(
+        &mut self,
+        samples: &[f32],
+        channels: usize,
+        rate: u32,
+        pts: gst::ClockTime,
+    ) -> Vec<Distortion> {
+        self.frames.start_buffer(rate, pts);
+        self.frames.push(samples, channels);
+
+        let mut measurements = Vec::new();
+        while let Some(frame) = self.frames.block(self.size) {
+            measurements.extend(self.analyse(frame));
+            self.frames.advance(self.size);
+        }
+        measurements
+    }
+
+    // Measurements of the frame at the current position.
+    fn analyse(&self, frame: &[f64]) -> Option<Distortion> {
+        let mut power = fft::power_spectrum(frame, &self.window);
+        let last = power.len() - 1;
+        for p in &mut power[1..last] {
+            *p *= 2.0;
+        }
+        let bin_width = f64::from(self.frames.rate()) / self.size as f64;
+
+        // Energy of the lobe around `bin`, limited to the spectrum and leaving DC out.
+        let lobe = |bin: usize| -> f64 {
+            let low = bin.saturating_sub(LOBE).max(LOBE + 1);
+            let high = (bin + LOBE).min(last);
+            if low > high {
+                0.0
+            } else {
+                power[low..=high].iter().sum()
+            }
+        };
+
+        // Look for the fundamental within 5% of the expected frequency.
+        let expected = self.frequency / bin_width;
+        let search = (expected * 0.05).max(3.0);
+        let low = ((expected - search).max(0.0) as usize).max(LOBE + 1);
+        let high = (expected + search).ceil() as usize;
+        if low > high || high > last {
+            return None;
+        }
+        let peak = (low..=high)
+            .filter(|bin| !power[*bin].is_nan())
+            .max_by(|a, b| power[*a].total_cmp(&power[*b]))?;
+
+        // Centre of gravity of the main lobe, closer than the peak bin.
+        let lobe_bins = peak.saturating_sub(LOBE).max(LOBE + 1)..=(peak + LOBE).min(last);
+        let fundamental: f64 = power[lobe_bins.clone()].iter().sum();
+        if fundamental <= 0.0 {
+            return None;
+        }
+        let centre = lobe_bins.map(|bin| bin as f64 * power[bin]).sum::<f64>() / fundamental;
+        let frequency = centre * bin_width;
+
+        // Harmonics are as far apart as the second is from the fundamental, their lobes
+        // must not overlap.
+        let second = (2.0 * centre).round() as usize;
+        if second.saturating_sub(LOBE) <= peak + LOBE {
+            return None;
+        }
+
+        let harmonics: f64 = (2..=MAX_HARMONIC)
+            .map(|harmonic| (harmonic as f64 * centre).round() as usize)
+            .take_while(|bin| *bin + LOBE <= last)
+            .map(lobe)
+            .sum();
+        let total: f64 = power[LOBE + 1..].iter().sum();
+        let residual = (total - fundamental).max(0.0);
+        let noise = (residual - harmonics).max(f64::MIN_POSITIVE);
+        if 10.0 * (fundamental / residual).log10() < MIN_TONE_DB {
+            return None;
+        }
+
+        // Mean square of the fundamental, given the energy of the window.
+        let level = fundamental / (self.size as f64 * self.window_power);
+        Some(Distortion {
+            start: gst::ClockTime::from_nseconds(self.frames.position()),
+            frequency,
+            level_db: 10.0 * level.log10(),
+            thd: (harmonics / fundamental).sqrt(),
+            thd_n: (residual / fundamental).sqrt(),
+            snr_db: 10.0 * (fundamental / noise).log10(),
+        })
+    }
+}
+
+impl SampleConsumer for DistortionAnalyser {
+    fn consume(&mut self, samples: &Samples) -> Result<(), Error> {
+        let measurements = self.process(
+            samples.data,
+            samples.channels(),
+            samples.info.rate(),
+            samples.pts,
+        );
+        for distortion in measurements {
+            samples.report(
+                samples
+                    .metric("distortion")
+                    .with_timestamp(distortion.start)
+                    .with_value("frequency", distortion.frequency)
+                    .with_value("level_db", distortion.level_db)
+                    .with_value("thd", distortion.thd)
+                    .with_value("thd_db", ratio_to_db(distortion.thd))
+                    .with_value("thd_n", distortion.thd_n)
+                    .with_value("thd_n_db", ratio_to_db(distortion.thd_n))
+                    .with_value("snr_db", distortion.snr_db),
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consumer::white_noise;
+    use std::f64::consts::PI;
+
+    const RATE: u32 = 48_000;
+
+    // One frame of a 1 kHz sine of amplitude 0.5 with a second harmonic `ratio` times
+    // its amplitude.
+    fn tone(ratio: f64) -> Vec<f32> {
+        (0..16384)
+            .map(|n| {
+                let t = n as f64 / f64::from(RATE);
+                let fundamental = (2.0 * PI * 1000.0 * t).sin();
+                let harmonic = ratio * (2.0 * PI * 2000.0 * t).sin();
+                (0.5 * (fundamental + harmonic)) as f32
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pure_sine() {
+        let mut analyser = DistortionAnalyser::new(1000.0);
+        let second = gst::ClockTime::from_seconds(2);
+        let measurements = analyser.process(&tone(0.0), 1, RATE, second);
+        assert_eq!(measurements.len(), 1);
+        let distortion = measurements[0];
+        assert_eq!(distortion.start, second);
+        assert!(
+            (distortion.frequency - 1000.0).abs() < 0.5,
+            "{:?}",
+            distortion
+        );
+        assert!(
+            (distortion.level_db + 9.03).abs() < 0.05,
+            "{:?}",
+            distortion
+        );
+        assert!(distortion.thd < 1e-4, "{:?}", distortion);
+        assert!(distortion.thd_n < 1e-4, "{:?}", distortion);
+    }
+
+    #[test]
+    fn second_harmonic() {
+        let mut analyser = DistortionAnalyser::new(1000.0);
+        let distortion = analyser.process(&tone(0.01), 1, RATE, gst::ClockTime::none())[0];
+        assert!((distortion.thd - 0.01).abs() < 1e-4, "{:?}", distortion);
+        assert!((distortion.thd_n - 0.01).abs() < 1e-4, "{:?}", distortion);
+        assert!(distortion.snr_db > 80.0, "{:?}", distortion);
+    }
+
+    #[test]
+    fn no_tone() {
+        let mut analyser = DistortionAnalyser::new(1000.0);
+        assert_eq!(
+            analyser.process(&[0.0; 16384], 1, RATE, gst::ClockTime::none()),
+            []
+        );
+        assert_eq!(
+            analyser.process(&white_noise(16384), 1, RATE, gst::ClockTime::none()),
+            []
+        );
+    }
+
+    #[test]
+    fn harmonics_within_fundamental_lobe() {
+        // 20 Hz is under 7 bins of 2.9 Hz, its second harmonic is in its lobe.
+        let mut analyser = DistortionAnalyser::new(20.0);
+        let samples = (0..16384)
+            .map(|n| (0.5 * (2.0 * PI * 20.0 * n as f64 / f64::from(RATE)).sin()) as f32)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            analyser.process(&samples, 1, RATE, gst::ClockTime::none()),
+            []
+        );
+    }
+}
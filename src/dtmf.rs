@@ -0,0 +1,371 @@
+use std::f64::consts::PI;
+
+use anyhow::Error;
+
+use crate::consumer::FrameAccumulator;
+use crate::{SampleConsumer, Samples};
+
+/// Name of the element message posted for every decoded digit, with the `digit` as a
+/// string, the `time` it started and its `duration`.
+pub const DTMF_MESSAGE: &str = "androidsink-dtmf";
+
+const ROWS: [f64; 4] = [697.0, 770.0, 852.0, 941.0];
+const COLUMNS: [f64; 4] = [1209.0, 1336.0, 1477.0, 1633.0];
+const KEYS: [[char; 4]; 4] = [
+    ['1', '2', '3', 'A'],
+    ['4', '5', '6', 'B'],
+    ['7', '8', '9', 'C'],
+    ['*', '0', '#', 'D'],
+];
+
+// Block length in seconds, the classic 205 samples at 8 kHz, which resolves the
+// closest tones apart.
+const BLOCK_DURATION: f64 = 205.0 / 8000.0;
+// Blocks quieter than this RMS in dBFS carry no digit.
+const MIN_LEVEL_DB: f64 = -40.0;
+// Share of the block energy which must be in the two detected tones.
+const MIN_TONE_RATIO: f64 = 0.7;
+// Maximum level difference between the two tones, in dB.
+const MAX_TWIST_DB: f64 = 8.0;
+// Minimum level difference between a tone and the next strongest of its group, in dB.
+const MIN_GROUP_MARGIN_DB: f64 = 8.0;
+// Consecutive blocks a digit must be seen in to be accepted, about 50 ms.
+const MIN_BLOCKS: usize = 2;
+
+/// Digit recognised by the `DtmfDecoder`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DtmfDigit {
+    /// One of `0`-`9`, `*`, `#` and `A`-`D`.
+    pub digit: char,
+    /// Buffer timestamp at which the tone started.
+    pub start: gst::ClockTime,
+    pub duration: gst::ClockTime,
+}
+
+impl DtmfDigit {
+    /// Structure of the element message reporting the digit.
+    pub fn to_structure(&self) -> gst::Structure {
+        gst::Structure::builder(DTMF_MESSAGE)
+            .field("digit", &self.digit.to_string())
+            .field("time", &self.start)
+            .field("duration", &self.duration)
+            .build()
+    }
+}
+
+// Power of `frequency` in `block` with the Goertzel algorithm, scaled to the mean
+// square the tone contributes to the block: a sine of amplitude `a` gives `a² / 2`.
+fn goertzel(block: &[f64], frequency: f64, rate: f64) -> f64 {
+    let coefficient = 2.0 * (2.0 * PI * frequency / rate).cos();
+    let (mut s1, mut s2) = (0.0, 0.0);
+    for x in block {
+        let s = x + coefficient * s1 - s2;
+        s2 = s1;
+        s1 = s;
+    }
+    let power = s1 * s1 + s2 * s2 - coefficient * s1 * s2;
+    2.0 * power / block.len().pow(2) as f64
+}
+
+// Index of the strongest tone of a group and whether it stands out from the others.
+fn strongest(powers: &[f64; 4]) -> (usize, f64, bool) {
+    let (index, power) = powers
+        .iter()
+        .copied()
+        .enumerate()
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .unwrap();
+    let margin = 10f64.powf(MIN_GROUP_MARGIN_DB / 10.0);
+    let clear = powers
+        .iter()
+        .enumerate()
+        .all(|(i, p)| i == index || p * margin < power);
+    (index, power, clear)
+}
+
+/// Goertzel based DTMF decoder over the channels mixed down to mono.
+#[derive(Debug, Default)]
+pub struct DtmfDecoder {
+    frames: FrameAccumulator,
+    size: usize,
+    // Digit of the current run of blocks, when it started and how many blocks long.
+    current: Option<(char, u64, usize)>,
+}
+
+impl DtmfDecoder {
+    pub fn new() -> Self {
+        DtmfDecoder::default()
+    }
+
+    /// Add interleaved `samples` with `channels` channels at `rate` Hz starting at
+    /// timestamp `pts`, and return the digits which ended in them.
+    pub fn process(
+        &mut self,
+        samples: &[f32],
+        channels: usize,
+        rate: u32,
+        pts: gst::ClockTime,
+    ) -> Vec<DtmfDigit> {
+        if self.frames.start_buffer(rate, pts) {
+            self.size = ((f64::from(rate) * BLOCK_DURATION).round() as usize).max(1);
+            self.current = None;
+        }
+        self.frames.push(samples, channels);
+
+        let mut digits = Vec::new();
+        while let Some(block) = self.frames.block(self.size) {
+            let digit = self.detect(block);
+            let start = self.frames.position();
+            self.frames.advance(self.size);
+
+            match self.current {
+                Some((current, _, ref mut blocks)) if Some(current) == digit => *blocks += 1,
+                _ => {
+                    digits.extend(self.end_digit(start));
+                    self.current = digit.map(|digit| (digit, start, 1));
+                }
+            }
+        }
+        digits
+    }
+
+    /// Digit still sounding at the end of the stream, ending it there.
+    pub fn finish(&mut self) -> Option<DtmfDigit> {
+        self.end_digit(self.frames.position())
+    }
+
+    // Digit of the current run ending at `end`, if it lasted long enough.
+    fn end_digit(&mut self, end: u64) -> Option<DtmfDigit> {
+        match self.current.take() {
+            Some((digit, start, blocks)) if blocks >= MIN_BLOCKS => Some(DtmfDigit {
+                digit,
+                start: gst::ClockTime::from_nseconds(start),
+                // Timestamps may go back between buffers.
+                duration: gst::ClockTime::from_nseconds(end.saturating_sub(start)),
+            }),
+            _ => None,
+        }
+    }
+
+    // Report `digits` as metrics and element messages.
+    fn report(samples: &Samples, digits: impl IntoIterator<Item = DtmfDigit>) -> Result<(), Error> {
+        for digit in digits {
+            samples.report(
+                samples
+                    .metric("dtmf")
+                    .with_timestamp(digit.start)
+                    .with_value("digit", digit.digit)
+                    .with_value("duration", digit.duration),
+            );
+            samples.post(digit.to_structure())?;
+        }
+
+        Ok(())
+    }
+
+    // Digit held by a block of mono samples, if any.
+    fn detect(&self, block: &[f64]) -> Option<char> {
+        let energy = block.iter().map(|x| x * x).sum::<f64>() / block.len() as f64;
+        if 10.0 * energy.log10() < MIN_LEVEL_DB {
+            return None;
+        }
+
+        let rate = f64::from(self.frames.rate());
+        let mut rows = [0.0; 4];
+        let mut columns = [0.0; 4];
+        for (power, frequency) in rows.iter_mut().zip(ROWS.iter()) {
+            *power = goertzel(block, *frequency, rate);
+        }
+        for (power, frequency) in columns.iter_mut().zip(COLUMNS.iter()) {
+            *power = goertzel(block, *frequency, rate);
+        }
+
+        let (row, row_power, row_clear) = strongest(&rows);
+        let (column, column_power, column_clear) = strongest(&columns);
+        let twist = 10.0 * (row_power / column_power).log10();
+        if row_clear
+            && column_clear
+            && twist.abs() <= MAX_TWIST_DB
+            && row_power + column_power >= MIN_TONE_RATIO * energy
+        {
+            Some(KEYS[row][column])
+        } else {
+            None
+        }
+    }
+}
+
+impl SampleConsumer for DtmfDecoder {
+    fn consume(&mut self, samples: &Samples) -> Result<(), Error> {
+        let digits = self.process(
+            samples.data,
+            samples.channels(),
+            samples.info.rate(),
+            samples.pts,
+        );
+        DtmfDecoder::report(samples, digits)
+    }
+
+    fn end_of_stream(&mut self, samples: &Samples) -> Result<(), Error> {
+        DtmfDecoder::report(samples, self.finish())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consumer::{white_noise, Collector};
+    use crate::{create_pipeline, Config, PipelineHandle};
+
+    // Tone pair of `digit` for `on` seconds followed by `off` seconds of silence.
+    fn tone(digit: char, on: f64, off: f64, rate: u32, out: &mut Vec<f32>) {
+        let (row, column) = (0..16)
+            .map(|i| (i / 4, i % 4))
+            .find(|(row, column)| KEYS[*row][*column] == digit)
+            .unwrap();
+        let rate = f64::from(rate);
+        for n in 0..(on * rate) as usize {
+            let t = n as f64 / rate;
+            let value = 0.25 * (2.0 * PI * ROWS[row] * t).sin()
+                + 0.25 * (2.0 * PI * COLUMNS[column] * t).sin();
+            out.push(value as f32);
+        }
+        out.resize(out.len() + (off * rate) as usize, 0.0);
+    }
+
+    // Digits decoded from mono `samples`, fed in buffers of 1024.
+    fn decode(samples: &[f32], rate: u32) -> Vec<DtmfDigit> {
+        let mut decoder = DtmfDecoder::new();
+        let mut digits = Vec::new();
+        for (index, chunk) in samples.chunks(1024).enumerate() {
+            let pts = index as u64 * 1024 * 1_000_000_000 / u64::from(rate);
+            digits.extend(decoder.process(chunk, 1, rate, gst::ClockTime::from_nseconds(pts)));
+        }
+        digits
+    }
+
+    #[test]
+    fn decodes_synthesised_digits() {
+        for &rate in &[8000, 44_100, 48_000] {
+            let mut samples = Vec::new();
+            for digit in "0123456789*#ABCD".chars() {
+                tone(digit, 0.1, 0.1, rate, &mut samples);
+            }
+
+            let digits = decode(&samples, rate);
+            let decoded: String = digits.iter().map(|digit| digit.digit).collect();
+            assert_eq!(decoded, "0123456789*#ABCD", "at {} Hz", rate);
+            for (index, digit) in digits.iter().enumerate() {
+                let start = digit.start.mseconds().unwrap() as i64;
+                let duration = digit.duration.mseconds().unwrap() as i64;
+                assert!((start - 200 * index as i64).abs() <= 30, "{:?}", digit);
+                assert!((duration - 100).abs() <= 30, "{:?}", digit);
+            }
+        }
+    }
+
+    #[test]
+    fn digit_ended_by_end_of_stream() {
+        let mut samples = Vec::new();
+        tone('7', 0.1, 0.0, 8000, &mut samples);
+        let mut decoder = DtmfDecoder::new();
+        let pts = gst::ClockTime::from_seconds(1);
+        assert_eq!(decoder.process(&samples, 1, 8000, pts), []);
+
+        let digit = decoder.finish().expect("digit sounding at the end");
+        assert_eq!(digit.digit, '7');
+        assert_eq!(digit.start, pts);
+        // The 3 blocks of 205 samples which fit in the tone.
+        assert_eq!(digit.duration, gst::ClockTime::from_nseconds(76_875_000));
+        assert_eq!(decoder.finish(), None);
+    }
+
+    #[test]
+    fn goertzel_gives_tone_mean_square() {
+        let rate = 8000.0;
+        let block = (0..205)
+            .map(|n| 0.5 * (2.0 * PI * 852.0 * n as f64 / rate).sin())
+            .collect::<Vec<_>>();
+        let power = goertzel(&block, 852.0, rate);
+        assert!((power - 0.125).abs() < 0.005, "{}", power);
+    }
+
+    #[test]
+    fn ignores_white_noise() {
+        assert_eq!(decode(&white_noise(8000 * 2), 8000), []);
+    }
+
+    #[test]
+    fn ignores_tone_pair_within_louder_partials() {
+        // The `1` pair, 697 Hz and 1209 Hz, along with stronger partials away from the
+        // DTMF frequencies, like a chord or a voiced sound: the pair is about 10% of
+        // the energy.
+        let rate = 8000.0;
+        let partials = [
+            (697.0, 0.1),
+            (1209.0, 0.1),
+            (300.0, 0.2),
+            (450.0, 0.2),
+            (2000.0, 0.2),
+            (2600.0, 0.2),
+            (3100.0, 0.2),
+        ];
+        let samples = (0..8000)
+            .map(|n| {
+                let t = n as f64 / rate;
+                partials
+                    .iter()
+                    .map(|(frequency, amplitude)| amplitude * (2.0 * PI * frequency * t).sin())
+                    .sum::<f64>() as f32
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(decode(&samples, 8000), []);
+    }
+
+    #[test]
+    fn ignores_single_tone() {
+        let rate = 8000.0;
+        let samples = (0..8000)
+            .map(|n| (0.5 * (2.0 * PI * 770.0 * n as f64 / rate).sin()) as f32)
+            .collect::<Vec<_>>();
+        assert_eq!(decode(&samples, 8000), []);
+    }
+
+    #[test]
+    fn ignores_nan() {
+        let mut samples = Vec::new();
+        tone('5', 0.2, 0.0, 8000, &mut samples);
+        samples.iter_mut().step_by(100).for_each(|x| *x = f32::NAN);
+        assert_eq!(decode(&samples, 8000), []);
+    }
+
+    #[test]
+    fn decodes_mixed_test_sources() {
+        gst::init().unwrap();
+
+        // The `9` key, 852 Hz and 1477 Hz, for 100 ms followed by 100 ms of silence.
+        let description = "audiotestsrc freq=852 volume=0.25 num-buffers=1 samplesperbuffer=800 \
+             ! audiomixer name=mix ! audio/x-raw,rate=8000 ! audioconvert \
+             audiotestsrc freq=1477 volume=0.25 num-buffers=1 samplesperbuffer=800 ! mix. \
+             audiotestsrc wave=silence num-buffers=2 samplesperbuffer=800 ! mix.";
+        let mut decoder = DtmfDecoder::new();
+        let (collector, digits) = Collector::new(move |samples: &Samples| {
+            let digits = decoder.process(
+                samples.data,
+                samples.channels(),
+                samples.info.rate(),
+                samples.pts,
+            );
+            digits.into_iter().map(|digit| digit.digit).collect()
+        });
+        let config = Config {
+            sample_rate: Some(8000),
+            ..Config::default()
+        };
+        let pipeline = create_pipeline(description, &config, vec![Box::new(collector)])
+            .expect("audiomixer pipeline");
+        PipelineHandle::new(pipeline).run().unwrap();
+
+        assert_eq!(*digits.lock().unwrap(), vec!['9']);
+    }
+}
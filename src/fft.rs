@@ -0,0 +1,87 @@
+use std::f64::consts::PI;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct Complex {
+    pub re: f64,
+    pub im: f64,
+}
+
+impl Complex {
+    fn mul(self, other: Complex) -> Complex {
+        Complex {
+            re: self.re * other.re - self.im * other.im,
+            im: self.re * other.im + self.im * other.re,
+        }
+    }
+
+    fn add(self, other: Complex) -> Complex {
+        Complex {
+            re: self.re + other.re,
+            im: self.im + other.im,
+        }
+    }
+
+    fn sub(self, other: Complex) -> Complex {
+        Complex {
+            re: self.re - other.re,
+            im: self.im - other.im,
+        }
+    }
+
+    pub fn norm_sqr(self) -> f64 {
+        self.re * self.re + self.im * self.im
+    }
+}
+
+// In-place iterative radix-2 FFT, `data.len()` must be a power of two.
+pub(crate) fn fft(data: &mut [Complex]) {
+    let n = data.len();
+    assert!(n.is_power_of_two(), "FFT size must be a power of two");
+
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            data.swap(i, j);
+        }
+    }
+
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * PI / len as f64;
+        let step = Complex {
+            re: angle.cos(),
+            im: angle.sin(),
+        };
+        for chunk in data.chunks_mut(len) {
+            let mut twiddle = Complex { re: 1.0, im: 0.0 };
+            let (low, high) = chunk.split_at_mut(len / 2);
+            for (a, b) in low.iter_mut().zip(high.iter_mut()) {
+                let t = b.mul(twiddle);
+                *b = a.sub(t);
+                *a = a.add(t);
+                twiddle = twiddle.mul(step);
+            }
+        }
+        len <<= 1;
+    }
+}
+
+// Squared magnitudes of bins 0 to N/2 of the FFT of `input` multiplied by `window`.
+pub(crate) fn power_spectrum(input: &[f64], window: &[f64]) -> Vec<f64> {
+    let mut data: Vec<Complex> = input
+        .iter()
+        .zip(window)
+        .map(|(x, w)| Complex { re: x * w, im: 0.0 })
+        .collect();
+    fft(&mut data);
+    data[..=input.len() / 2]
+        .iter()
+        .map(|bin| bin.norm_sqr())
+        .collect()
+}
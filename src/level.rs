@@ -0,0 +1,265 @@
+use std::time::Duration;
+
+use anyhow::Error;
+
+use crate::{SampleConsumer, Samples};
+
+// Polyphase FIR for 4x oversampling, one row of 12 taps per phase.
+// Coefficients from ITU-R BS.1770-4, Annex 2.
+const TRUE_PEAK_FILTER: [[f32; 12]; 4] = [
+    [
+        0.001_708_984_4,
+        0.010_986_328,
+        -0.019_653_32,
+        0.033_203_125,
+        -0.059_448_242,
+        0.137_329_1,
+        0.972_167_97,
+        -0.102_294_92,
+        0.047_607_42,
+        -0.026_611_328,
+        0.014_892_578,
+        -0.008_300_781,
+    ],
+    [
+        -0.029_174_805,
+        0.029_296_875,
+        -0.051_757_813,
+        0.089_111_33,
+        -0.166_503_9,
+        0.465_087_9,
+        0.779_785_16,
+        -0.200_317_38,
+        0.101_562_5,
+        -0.058_227_54,
+        0.033_081_055,
+        -0.018_920_898,
+    ],
+    [
+        -0.018_920_898,
+        0.033_081_055,
+        -0.058_227_54,
+        0.101_562_5,
+        -0.200_317_38,
+        0.779_785_16,
+        0.465_087_9,
+        -0.166_503_9,
+        0.089_111_33,
+        -0.051_757_813,
+        0.029_296_875,
+        -0.029_174_805,
+    ],
+    [
+        -0.008_300_781,
+        0.014_892_578,
+        -0.026_611_328,
+        0.047_607_42,
+        -0.102_294_92,
+        0.972_167_97,
+        0.137_329_1,
+        -0.059_448_242,
+        0.033_203_125,
+        -0.019_653_32,
+        0.010_986_328,
+        0.001_708_984_4,
+    ],
+];
+
+const TAPS: usize = 12;
+
+/// Convert a linear amplitude to dB relative to full scale.
+pub fn to_db(amplitude: f64) -> f64 {
+    20.0 * amplitude.log10()
+}
+
+/// Levels of one channel over one buffer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChannelLevel {
+    /// Root mean square in dBFS.
+    pub rms_db: f64,
+    /// Highest absolute sample value in dBFS.
+    pub peak_db: f64,
+    /// Highest absolute value of the 4x oversampled signal in dBTP.
+    pub true_peak_db: f64,
+    /// Peak held for the hold time, then decaying, in dBFS.
+    pub peak_hold_db: f64,
+}
+
+#[derive(Debug)]
+struct ChannelState {
+    // Last input samples, newest first, feeding the oversampling filter.
+    history: [f32; TAPS],
+    peak_hold_db: f64,
+    // Time since the held peak was last reached, in seconds.
+    peak_hold_age: f64,
+}
+
+impl Default for ChannelState {
+    fn default() -> Self {
+        ChannelState {
+            history: [0.0; TAPS],
+            peak_hold_db: f64::NEG_INFINITY,
+            peak_hold_age: 0.0,
+        }
+    }
+}
+
+/// Level meter giving the same figures as the `level` element, plus true peak,
+/// for every buffer and channel.
+#[derive(Debug)]
+pub struct LevelMeter {
+    hold_time: f64,
+    decay: f64,
+    channels: Vec<ChannelState>,
+}
+
+impl Default for LevelMeter {
+    /// Hold peaks for 300 ms and decay them by 10 dB per second, like `level`.
+    fn default() -> Self {
+        LevelMeter::new(Duration::from_millis(300), 10.0)
+    }
+}
+
+impl LevelMeter {
+    /// Create a meter holding peaks for `hold_time` before they decay by `decay` dB
+    /// per second.
+    pub fn new(hold_time: Duration, decay: f64) -> Self {
+        LevelMeter {
+            hold_time: hold_time.as_secs_f64(),
+            decay,
+            channels: Vec::new(),
+        }
+    }
+
+    /// Measure interleaved `samples` with `channels` channels at `rate` Hz, carrying
+    /// the filter and peak hold state over from the previous call.
+    pub fn process(&mut self, samples: &[f32], channels: usize, rate: u32) -> Vec<ChannelLevel> {
+        if self.channels.len() != channels {
+            self.channels = (0..channels).map(|_| ChannelState::default()).collect();
+        }
+
+        let frames = samples.len() / channels;
+        let elapsed = frames as f64 / f64::from(rate);
+        let (hold_time, decay) = (self.hold_time, self.decay);
+        self.channels
+            .iter_mut()
+            .enumerate()
+            .map(|(channel, state)| {
+                let mut sum = 0f64;
+                let mut peak = 0f32;
+                let mut true_peak = 0f32;
+                for &sample in samples.iter().skip(channel).step_by(channels) {
+                    sum += f64::from(sample) * f64::from(sample);
+                    peak = peak.max(sample.abs());
+
+                    state.history.rotate_right(1);
+                    state.history[0] = sample;
+                    for phase in TRUE_PEAK_FILTER.iter() {
+                        let value: f32 = phase
+                            .iter()
+                            .zip(state.history.iter())
+                            .map(|(coefficient, sample)| coefficient * sample)
+                            .sum();
+                        true_peak = true_peak.max(value.abs());
+                    }
+                }
+
+                let peak_db = to_db(f64::from(peak));
+                if peak_db >= state.peak_hold_db {
+                    state.peak_hold_db = peak_db;
+                    state.peak_hold_age = 0.0;
+                } else {
+                    // Only the part of this buffer past the hold time decays the peak.
+                    let age = state.peak_hold_age + elapsed;
+                    let decaying =
+                        (age - hold_time).max(0.0) - (state.peak_hold_age - hold_time).max(0.0);
+                    state.peak_hold_age = age;
+                    state.peak_hold_db = (state.peak_hold_db - decay * decaying).max(peak_db);
+                }
+
+                ChannelLevel {
+                    rms_db: to_db((sum / frames as f64).sqrt()),
+                    peak_db,
+                    true_peak_db: to_db(f64::from(true_peak.max(peak))),
+                    peak_hold_db: state.peak_hold_db,
+                }
+            })
+            .collect()
+    }
+}
+
+impl SampleConsumer for LevelMeter {
+    fn consume(&mut self, samples: &Samples) -> Result<(), Error> {
+        let levels = self.process(samples.data, samples.channels(), samples.info.rate());
+        for (channel, level) in levels.iter().enumerate() {
+            samples.report(
+                samples
+                    .metric("level")
+                    .with_channel(samples.channel_name(channel))
+                    .with_value("rms_db", level.rms_db)
+                    .with_value("peak_db", level.peak_db)
+                    .with_value("true_peak_db", level.true_peak_db)
+                    .with_value("peak_hold_db", level.peak_hold_db),
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    const RATE: u32 = 48_000;
+
+    // 100 ms of a mono sine of `frequency` Hz and amplitude 1, starting at `phase`.
+    fn sine(frequency: f64, phase: f64) -> Vec<f32> {
+        (0..RATE / 10)
+            .map(|n| (2.0 * PI * frequency * f64::from(n) / f64::from(RATE) + phase).sin() as f32)
+            .collect()
+    }
+
+    #[test]
+    fn full_scale_sine() {
+        // A 1 kHz sine has a sample on every crest at 48 kHz.
+        let level = LevelMeter::default().process(&sine(1000.0, 0.0), 1, RATE)[0];
+        assert!(level.peak_db.abs() < 0.001, "{:?}", level);
+        assert!((level.rms_db + 3.0103).abs() < 0.001, "{:?}", level);
+        assert!(level.true_peak_db.abs() < 0.1, "{:?}", level);
+    }
+
+    #[test]
+    fn inter_sample_peak() {
+        // Samples of a quarter rate sine shifted by 45° all miss the crests by 3 dB.
+        let level =
+            LevelMeter::default().process(&sine(f64::from(RATE) / 4.0, PI / 4.0), 1, RATE)[0];
+        assert!((level.peak_db + 3.0103).abs() < 0.001, "{:?}", level);
+        assert!(level.true_peak_db.abs() < 0.3, "{:?}", level);
+    }
+
+    #[test]
+    fn peak_hold_decay() {
+        // Held for 300 ms, then decaying by 10 dB per second.
+        let mut meter = LevelMeter::default();
+        let buffer = |value| vec![value; RATE as usize / 10];
+        assert_eq!(meter.process(&buffer(1.0), 1, RATE)[0].peak_hold_db, 0.0);
+        let held = (0..8)
+            .map(|_| meter.process(&buffer(0.01), 1, RATE)[0].peak_hold_db)
+            .collect::<Vec<_>>();
+        for (held, expected) in held
+            .iter()
+            .zip(&[0.0, 0.0, 0.0, -1.0, -2.0, -3.0, -4.0, -5.0])
+        {
+            assert!((held - expected).abs() < 1e-9, "{:?}", held);
+        }
+
+        // Never below the current peak, and reset by a louder one.
+        for _ in 0..50 {
+            meter.process(&buffer(0.01), 1, RATE);
+        }
+        assert!((meter.process(&buffer(0.01), 1, RATE)[0].peak_hold_db + 40.0).abs() < 1e-6);
+        assert!((meter.process(&buffer(0.5), 1, RATE)[0].peak_hold_db - to_db(0.5)).abs() < 1e-6);
+    }
+}
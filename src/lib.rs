@@ -1,30 +1,93 @@
 #[macro_use]
 extern crate gst;
 
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
 use gst::gst_element_error;
 use gst::prelude::*;
 
-use byte_slice_cast::*;
-
-use std::i16;
-use std::i32;
-
 use anyhow::Error;
 use derive_more::{Display, Error};
 
+pub mod clip;
+mod consumer;
+mod convert;
+pub mod distortion;
+pub mod dtmf;
+mod fft;
+pub mod level;
+pub mod loudness;
+pub mod metrics;
+pub mod pitch;
+pub mod platform;
+pub mod plugins;
+pub mod record;
+pub mod silence;
+pub mod spectrum;
+pub mod vad;
+pub use consumer::{Rms, SampleConsumer, Samples};
+pub use convert::FORMATS;
+
 #[derive(Debug, Display, Error)]
 #[display(fmt = "Missing element {}", _0)]
-struct MissingElement(#[error(not(source))] &'static str);
+pub struct MissingElement(#[error(not(source))] pub String);
+
+#[derive(Debug, Display, Error)]
+#[display(fmt = "Invalid pipeline description at {:?}: {}", fragment, error)]
+pub struct InvalidDescription {
+    pub fragment: String,
+    pub error: String,
+    source: glib::Error,
+}
 
 #[derive(Debug, Display, Error)]
 #[display(fmt = "Received error from {}: {} (debug: {:?})", src, error, debug)]
-struct ErrorMessage {
-    src: String,
-    error: String,
-    debug: Option<String>,
+pub struct ErrorMessage {
+    pub src: String,
+    pub error: String,
+    pub debug: Option<String>,
     source: glib::Error,
 }
 
+/// Error returned when running a pipeline.
+#[derive(Debug, Display)]
+pub enum PipelineError {
+    MissingElement(MissingElement),
+    InvalidDescription(InvalidDescription),
+    /// An error message posted on the pipeline's bus.
+    Message(ErrorMessage),
+    Other(Error),
+}
+
+impl std::error::Error for PipelineError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PipelineError::MissingElement(err) => Some(err),
+            PipelineError::InvalidDescription(err) => Some(err),
+            PipelineError::Message(err) => Some(err),
+            PipelineError::Other(err) => Some(err.as_ref()),
+        }
+    }
+}
+
+impl From<Error> for PipelineError {
+    fn from(err: Error) -> Self {
+        let err = match err.downcast::<ErrorMessage>() {
+            Ok(err) => return PipelineError::Message(err),
+            Err(err) => err,
+        };
+        let err = match err.downcast::<MissingElement>() {
+            Ok(err) => return PipelineError::MissingElement(err),
+            Err(err) => err,
+        };
+        match err.downcast::<InvalidDescription>() {
+            Ok(err) => PipelineError::InvalidDescription(err),
+            Err(err) => PipelineError::Other(err),
+        }
+    }
+}
+
 use once_cell::sync::Lazy;
 
 pub static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
@@ -35,47 +98,222 @@ pub static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
     )
 });
 
-fn create_pipeline() -> Result<gst::Pipeline, Error> {
-    gst_log!(CAT, "creating pipeline");
+/// Source used by `run` when no pipeline description is given.
+pub const DEFAULT_DESCRIPTION: &str = "audiotestsrc";
+
+/// Audio format requested on the analysis appsink, whether to record it and where the
+/// measurements go.
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    /// Sample rate in Hz, any rate is accepted when `None`.
+    pub sample_rate: Option<u32>,
+    /// Samples per buffer, applied to every source element with a `samplesperbuffer`
+    /// property. The source's default is kept when `None`.
+    pub samples_per_buffer: Option<u32>,
+    /// Number of interleaved channels, any count is accepted when `None`.
+    pub channels: Option<u32>,
+    /// Record the analysed stream to files, through a tee in front of the appsink.
+    pub record: Option<record::RecordConfig>,
+    /// Receives the metrics of every analyser, by default they are printed.
+    pub metrics: metrics::Reporter,
+}
+
+// Split `text` at the characters matching `separator`, ignoring those inside quoted
+// property values, and trim the parts.
+fn split_unquoted(text: &str, separator: impl Fn(char) -> bool) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    for (i, c) in text.char_indices() {
+        if c == '"' {
+            quoted = !quoted;
+        } else if !quoted && separator(c) {
+            parts.push(text[start..i].trim());
+            start = i + c.len_utf8();
+        }
+    }
+    parts.push(text[start..].trim());
+    parts
+}
+
+// Split a gst-launch style description into its `!` separated fragments.
+fn description_fragments(description: &str) -> Vec<&str> {
+    split_unquoted(description, |c| c == '!')
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-'
+}
+
+// Factory and `name` property of every element a description fragment creates. Caps,
+// pad references and bins are skipped.
+fn fragment_elements(fragment: &str) -> Vec<(&str, Option<&str>)> {
+    let mut elements: Vec<(&str, Option<&str>)> = Vec::new();
+    for token in split_unquoted(fragment, char::is_whitespace) {
+        if let Some(name) = token.strip_prefix("name=") {
+            if let Some(element) = elements.last_mut() {
+                element.1 = Some(name.trim_matches('"'));
+            }
+        } else if !token.is_empty() && token.chars().all(is_name_char) {
+            elements.push((token, None));
+        }
+    }
+    elements
+}
+
+// Fragment of the description creating the element named in a parser error `message`,
+// either by its `name` or by the factory name and number the parser gave it. `None`
+// when no fragment or several could be meant.
+fn blamed_fragment<'a>(description: &'a str, message: &str) -> Option<&'a str> {
+    let words: Vec<&str> = message.split(|c| !is_name_char(c)).collect();
+    let mut named = description_fragments(description)
+        .into_iter()
+        .filter(|fragment| {
+            fragment_elements(fragment)
+                .into_iter()
+                .any(|(factory, name)| match name {
+                    Some(name) => words.contains(&name),
+                    None => words.iter().any(|word| {
+                        word.strip_prefix(factory).is_some_and(|number| {
+                            !number.is_empty() && number.chars().all(|c| c.is_ascii_digit())
+                        })
+                    }),
+                })
+        });
+    match (named.next(), named.next()) {
+        (Some(fragment), None) => Some(fragment),
+        _ => None,
+    }
+}
+
+// Parse the description into a bin with its unlinked pads ghosted, mapping parser
+// failures to `MissingElement` or `InvalidDescription`.
+fn parse_description(description: &str) -> Result<gst::Element, Error> {
+    let mut context = gst::ParseContext::new();
+    gst::parse_bin_from_description_full(
+        description,
+        true,
+        Some(&mut context),
+        gst::ParseFlags::empty(),
+    )
+    .map_err(|err| {
+        if let Some(name) = context.get_missing_elements().into_iter().next() {
+            return MissingElement(name).into();
+        }
+
+        // The parser does not tell where it failed, at best which element it failed
+        // on. Fragments cannot be parsed on their own to find out, as they may refer
+        // to other fragments.
+        let error = err.to_string();
+        let fragment = blamed_fragment(description, &error).unwrap_or(description);
+        InvalidDescription {
+            fragment: String::from(fragment),
+            error,
+            source: err,
+        }
+        .into()
+    })
+}
+
+// Set `samplesperbuffer` on every element of the parsed description which has it.
+fn set_samples_per_buffer(src: &gst::Element, samples_per_buffer: u32) -> Result<(), Error> {
+    let bin = src
+        .clone()
+        .dynamic_cast::<gst::Bin>()
+        .expect("Parsed description is expected to be a bin!");
+    for element in bin.iterate_recurse() {
+        let element = element?;
+        if element.find_property("samplesperbuffer").is_some() {
+            element.set_property("samplesperbuffer", &(samples_per_buffer as i32))?;
+        }
+    }
+    Ok(())
+}
+
+// State shared by the callbacks of the analysis appsink.
+struct Analysis {
+    consumers: Vec<Box<dyn SampleConsumer>>,
+    // Last sample handed to the consumers and its format, for the end of the stream.
+    last: Option<(gst::Sample, gst_audio::AudioInfo)>,
+}
+
+/// Create a pipeline from a gst-launch style description, e.g.
+/// `filesrc location=x.wav ! decodebin ! audioconvert ! audioresample`, and link its
+/// unconnected source pad to the analysis appsink. Every buffer reaching the appsink is
+/// handed to `consumers`.
+pub fn create_pipeline(
+    description: &str,
+    config: &Config,
+    consumers: Vec<Box<dyn SampleConsumer>>,
+) -> Result<gst::Pipeline, Error> {
+    gst_log!(
+        CAT,
+        "creating pipeline from {:?} with {:?}",
+        description,
+        config
+    );
     let pipeline = gst::Pipeline::new(None);
-    gst_trace!(CAT, "creating audiotestsrc");
-    let src = gst::ElementFactory::make("audiotestsrc", None)
-        .map_err(|_| MissingElement("audiotestsrc"))?;
+    gst_trace!(CAT, "parsing description");
+    let src = parse_description(description)?;
+    if let Some(samples_per_buffer) = config.samples_per_buffer {
+        gst_trace!(CAT, "set samplesperbuffer");
+        set_samples_per_buffer(&src, samples_per_buffer)?;
+    }
     gst_trace!(CAT, "creating appsink");
-    let sink = gst::ElementFactory::make("appsink", None).map_err(|_| MissingElement("appsink"))?;
+    let sink = gst::ElementFactory::make("appsink", None)
+        .map_err(|_| MissingElement(String::from("appsink")))?;
 
     gst_trace!(CAT, "add src and sink");
     pipeline.add_many(&[&src, &sink])?;
-    gst_trace!(CAT, "link src and sink");
-    src.link(&sink)?;
+    match config.record {
+        Some(ref record) => {
+            gst_trace!(CAT, "link src and sink with recording");
+            record::link_recording(&pipeline, &src, &sink, record)?;
+        }
+        None => {
+            gst_trace!(CAT, "link src and sink");
+            src.link(&sink)?;
+        }
+    }
 
     gst_trace!(CAT, "cast sink to Appsink");
     let appsink = sink
         .dynamic_cast::<gst_app::AppSink>()
         .expect("Sink element is expected to be an appsink!");
 
-    // Tell the appsink what format we want. It will then be the audiotestsrc's job to
+    // Tell the appsink what format we want. It will then be the source's job to
     // provide the format we request.
     // This can be set after linking the two objects, because format negotiation between
     // both elements will happen during pre-rolling of the pipeline.
     gst_trace!(CAT, "set caps");
-    appsink.set_caps(Some(&gst::Caps::new_simple(
-        "audio/x-raw",
-        &[
-            ("format", &gst_audio::AUDIO_FORMAT_S16.to_str()),
-            ("layout", &"interleaved"),
-            ("channels", &(1i32)),
-            ("rate", &gst::IntRange::<i32>::new(1, i32::MAX)),
-        ],
-    )));
+    let caps = gst::Caps::builder("audio/x-raw")
+        .field("format", &convert::formats_list())
+        .field("layout", &"interleaved");
+    let caps = match config.channels {
+        Some(channels) => caps.field("channels", &(channels as i32)),
+        None => caps.field("channels", &gst::IntRange::<i32>::new(1, i32::MAX)),
+    };
+    let caps = match config.sample_rate {
+        Some(rate) => caps.field("rate", &(rate as i32)),
+        None => caps.field("rate", &gst::IntRange::<i32>::new(1, i32::MAX)),
+    };
+    appsink.set_caps(Some(&caps.build()));
 
     // Getting data out of the appsink is done by setting callbacks on it.
     // The appsink will then call those handlers, as soon as data is available.
     gst_trace!(CAT, "set callbacks");
+    let mut data = Vec::new();
+    let reporter = config.metrics.clone();
+    let analysis = Arc::new(Mutex::new(Analysis {
+        consumers,
+        last: None,
+    }));
+    let eos_analysis = analysis.clone();
+    let eos_reporter = reporter.clone();
     appsink.set_callbacks(
         gst_app::AppSinkCallbacks::builder()
             // Add a handler to the "new-sample" signal.
-            .new_sample(|appsink| {
+            .new_sample(move |appsink| {
                 // Pull the sample in question out of the appsink's buffer.
                 let sample = appsink.pull_sample().map_err(|_| gst::FlowError::Eos)?;
                 let buffer = sample.get_buffer().ok_or_else(|| {
@@ -105,33 +343,102 @@ fn create_pipeline() -> Result<gst::Pipeline, Error> {
                     gst::FlowError::Error
                 })?;
 
-                // We know what format the data in the memory region has, since we requested
-                // it by setting the appsink's caps. So what we do here is interpret the
-                // memory region we mapped as an array of signed 16 bit integers.
-                let samples = map.as_slice_of::<i16>().map_err(|_| {
+                // The appsink accepts several sample formats, so look up which one was
+                // negotiated for this sample before interpreting the memory region.
+                let info = sample
+                    .get_caps()
+                    .and_then(|caps| gst_audio::AudioInfo::from_caps(caps).ok())
+                    .ok_or_else(|| {
+                        gst_element_error!(
+                            appsink,
+                            gst::ResourceError::Failed,
+                            ("Failed to get audio info from sample caps")
+                        );
+
+                        gst::FlowError::Error
+                    })?;
+
+                // Analysers work on f32 samples regardless of the negotiated format.
+                convert::normalize(info.format(), &map, &mut data).map_err(|err| {
                     gst_element_error!(
                         appsink,
                         gst::ResourceError::Failed,
-                        ("Failed to interprete buffer as S16 PCM")
+                        (
+                            "Failed to interprete buffer as {} PCM",
+                            info.format().to_str()
+                        ),
+                        ["{}", err]
                     );
 
                     gst::FlowError::Error
                 })?;
 
-                // For buffer (= chunk of samples), we calculate the root mean square:
-                // (https://en.wikipedia.org/wiki/Root_mean_square)
-                let sum: f64 = samples
-                    .iter()
-                    .map(|sample| {
-                        let f = f64::from(*sample) / f64::from(i16::MAX);
-                        f * f
-                    })
-                    .sum();
-                let rms = (sum / (samples.len() as f64)).sqrt();
-                glib::g_print!("rms: {}", rms);
+                let stream_id = appsink
+                    .get_static_pad("sink")
+                    .and_then(|pad| pad.get_stream_id())
+                    .map(String::from)
+                    .unwrap_or_default();
+                let samples = Samples {
+                    data: &data,
+                    info: &info,
+                    sample: &sample,
+                    pts: buffer.get_pts(),
+                    duration: buffer.get_duration(),
+                    sink: appsink.upcast_ref(),
+                    stream_id: &stream_id,
+                    reporter: &reporter,
+                };
+                let mut analysis = analysis.lock().unwrap();
+                for consumer in analysis.consumers.iter_mut() {
+                    consumer.consume(&samples).map_err(|err| {
+                        gst_element_error!(
+                            appsink,
+                            gst::ResourceError::Failed,
+                            ("Sample consumer failed"),
+                            ["{}", err]
+                        );
+
+                        gst::FlowError::Error
+                    })?;
+                }
+                analysis.last = Some((sample.clone(), info.clone()));
 
                 Ok(gst::FlowSuccess::Ok)
             })
+            .eos(move |appsink| {
+                let mut analysis = eos_analysis.lock().unwrap();
+                let Analysis { consumers, last } = &mut *analysis;
+                // Nothing can be in progress before the first buffer.
+                let (sample, info) = match last {
+                    Some((sample, info)) => (sample, info),
+                    None => return,
+                };
+                let stream_id = appsink
+                    .get_static_pad("sink")
+                    .and_then(|pad| pad.get_stream_id())
+                    .map(String::from)
+                    .unwrap_or_default();
+                let samples = Samples {
+                    data: &[],
+                    info,
+                    sample,
+                    pts: gst::ClockTime::none(),
+                    duration: gst::ClockTime::none(),
+                    sink: appsink.upcast_ref(),
+                    stream_id: &stream_id,
+                    reporter: &eos_reporter,
+                };
+                for consumer in consumers.iter_mut() {
+                    if let Err(err) = consumer.end_of_stream(&samples) {
+                        gst_element_error!(
+                            appsink,
+                            gst::ResourceError::Failed,
+                            ("Sample consumer failed at the end of the stream"),
+                            ["{}", err]
+                        );
+                    }
+                }
+            })
             .build(),
     );
 
@@ -139,6 +446,13 @@ fn create_pipeline() -> Result<gst::Pipeline, Error> {
     Ok(pipeline)
 }
 
+// Name of the application message posted by `PipelineHandle::stop`.
+const STOP_MESSAGE: &str = "androidsink-stop";
+
+// How long the pipeline is given to drain after a stop request before it is shut down
+// without reaching EOS, e.g. when it is paused.
+const STOP_TIMEOUT: Duration = Duration::from_secs(2);
+
 fn main_loop(pipeline: gst::Pipeline) -> Result<(), Error> {
     gst_log!(CAT, "set pipeline state to playing");
     pipeline.set_state(gst::State::Playing)?;
@@ -148,11 +462,48 @@ fn main_loop(pipeline: gst::Pipeline) -> Result<(), Error> {
         .expect("Pipeline without bus. Shouldn't happen!");
 
     gst_log!(CAT, "entering main loop");
-    for msg in bus.iter_timed(gst::CLOCK_TIME_NONE) {
+    // Set once a stop is requested, to wait for the EOS no longer than that.
+    let mut deadline: Option<Instant> = None;
+    loop {
         use gst::MessageView;
 
+        let timeout = match deadline {
+            Some(deadline) => {
+                let left = deadline.saturating_duration_since(Instant::now());
+                gst::ClockTime::from_nseconds(left.as_nanos() as u64)
+            }
+            None => gst::CLOCK_TIME_NONE,
+        };
+        let msg = match bus.timed_pop(timeout) {
+            Some(msg) => msg,
+            None => {
+                gst_warning!(CAT, "no EOS after the stop request, shutting down");
+                break;
+            }
+        };
+
         match msg.view() {
             MessageView::Eos(..) => break,
+            MessageView::Application(..)
+                if msg
+                    .get_structure()
+                    .is_some_and(|s| s.get_name() == STOP_MESSAGE) =>
+            {
+                gst_log!(CAT, "stop requested, waiting for EOS");
+                deadline.get_or_insert_with(|| Instant::now() + STOP_TIMEOUT);
+            }
+            MessageView::Element(..) => {
+                if let Some(s) = msg.get_structure() {
+                    match s.get_name() {
+                        clip::CLIP_MESSAGE
+                        | silence::SILENCE_START_MESSAGE
+                        | silence::DROPOUT_MESSAGE => {
+                            gst_warning!(CAT, "{}", s)
+                        }
+                        _ => gst_info!(CAT, "{}", s),
+                    }
+                }
+            }
             MessageView::Error(err) => {
                 pipeline.set_state(gst::State::Null)?;
                 return Err(ErrorMessage {
@@ -176,72 +527,332 @@ fn main_loop(pipeline: gst::Pipeline) -> Result<(), Error> {
     Ok(())
 }
 
-pub fn run() {
-    match create_pipeline().and_then(main_loop) {
-        Ok(r) => r,
-        Err(e) => gst_trace!(CAT, "{}:{}:{}", file!(), line!(), e),
+/// Handle to a pipeline which can be driven from other threads while `run` blocks.
+#[derive(Clone, Debug)]
+pub struct PipelineHandle {
+    pipeline: gst::Pipeline,
+}
+
+impl PipelineHandle {
+    pub fn new(pipeline: gst::Pipeline) -> Self {
+        PipelineHandle { pipeline }
+    }
+
+    pub fn pipeline(&self) -> &gst::Pipeline {
+        &self.pipeline
+    }
+
+    /// Set the pipeline to playing and block until EOS, an error or `stop`.
+    pub fn run(&self) -> Result<(), PipelineError> {
+        main_loop(self.pipeline.clone()).map_err(PipelineError::from)
+    }
+
+    /// Make `run` shut the pipeline down and return, once EOS has gone through it so
+    /// that analysers report what is still in progress and recordings are finalised.
+    /// The pipeline is shut down anyway if EOS does not come through in time.
+    pub fn stop(&self) {
+        gst_log!(CAT, "request stop");
+        let msg = gst::message::Application::new(gst::Structure::new_empty(STOP_MESSAGE));
+        if self.pipeline.post_message(msg).is_err() {
+            gst_warning!(CAT, "could not post stop message");
+        }
+        if !self.pipeline.send_event(gst::event::Eos::new()) {
+            gst_warning!(CAT, "could not send EOS");
+        }
+    }
+
+    pub fn pause(&self) -> Result<(), Error> {
+        gst_log!(CAT, "set pipeline state to paused");
+        self.pipeline.set_state(gst::State::Paused)?;
+        Ok(())
+    }
+
+    pub fn resume(&self) -> Result<(), Error> {
+        gst_log!(CAT, "set pipeline state to playing");
+        self.pipeline.set_state(gst::State::Playing)?;
+        Ok(())
     }
 }
 
+pub fn run() -> Result<(), PipelineError> {
+    run_description(DEFAULT_DESCRIPTION)
+}
+
+pub fn run_description(description: &str) -> Result<(), PipelineError> {
+    let pipeline = create_pipeline(description, &Config::default(), vec![Box::new(Rms)])?;
+    PipelineHandle::new(pipeline).run()
+}
+
 #[cfg(target_os = "android")]
 #[allow(non_snake_case)]
 pub mod android {
     mod gstinit;
+    mod pcm;
+    mod session;
     use crate::CAT;
-    use jni::objects::JClass;
-    use jni::sys::jint;
+    use jni::objects::{JClass, JObject, JString};
+    use jni::sys::{jboolean, jint, jlong};
     use jni::{JNIEnv, JavaVM};
     use libc::c_void;
 
-    static mut RUNNING: bool = false;
+    #[no_mangle]
+    pub extern "C" fn Java_tw_mapacode_androidsink_AndroidSink_nativeCreate(
+        _env: JNIEnv,
+        _: JClass,
+    ) -> jlong {
+        session::create()
+    }
+
+    #[no_mangle]
+    pub extern "C" fn Java_tw_mapacode_androidsink_AndroidSink_nativeDestroy(
+        _env: JNIEnv,
+        _: JClass,
+        handle: jlong,
+    ) {
+        session::destroy(handle);
+    }
+
+    #[no_mangle]
+    pub extern "C" fn Java_tw_mapacode_androidsink_AndroidSink_nativeSetErrorListener(
+        env: JNIEnv,
+        _: JClass,
+        handle: jlong,
+        listener: JObject,
+    ) {
+        if let Some(session) = session::get(handle) {
+            if listener.is_null() {
+                session.set_error_listener(None);
+            } else {
+                match env.new_global_ref(listener) {
+                    Ok(listener) => session.set_error_listener(Some(listener)),
+                    Err(e) => gst_warning!(CAT, "could not keep error listener: {}", e),
+                }
+            }
+        }
+    }
+
+    #[no_mangle]
+    pub extern "C" fn Java_tw_mapacode_androidsink_AndroidSink_nativeSetMetricsListener(
+        env: JNIEnv,
+        _: JClass,
+        handle: jlong,
+        bridge: JObject,
+    ) {
+        if let Some(session) = session::get(handle) {
+            if bridge.is_null() {
+                session.set_metrics_listener(None);
+            } else {
+                match env.new_global_ref(bridge) {
+                    Ok(bridge) => session.set_metrics_listener(Some(bridge)),
+                    Err(e) => gst_warning!(CAT, "could not keep metrics listener: {}", e),
+                }
+            }
+        }
+    }
+
+    #[no_mangle]
+    pub extern "C" fn Java_tw_mapacode_androidsink_AndroidSink_nativeSetPcmListener(
+        env: JNIEnv,
+        _: JClass,
+        handle: jlong,
+        bridge: JObject,
+    ) {
+        if let Some(session) = session::get(handle) {
+            if bridge.is_null() {
+                session.set_pcm_listener(None);
+            } else {
+                match env.new_global_ref(bridge) {
+                    Ok(bridge) => session.set_pcm_listener(Some(bridge)),
+                    Err(e) => gst_warning!(CAT, "could not keep PCM listener: {}", e),
+                }
+            }
+        }
+    }
+
+    #[no_mangle]
+    pub extern "C" fn Java_tw_mapacode_androidsink_AndroidSink_nativeReleasePcm(
+        _env: JNIEnv,
+        _: JClass,
+        token: jlong,
+    ) {
+        pcm::release(token);
+    }
+
+    // Non-positive values restore the default: any rate is accepted.
+    #[no_mangle]
+    pub extern "C" fn Java_tw_mapacode_androidsink_AndroidSink_nativeSetSampleRate(
+        _env: JNIEnv,
+        _: JClass,
+        handle: jlong,
+        sample_rate: jint,
+    ) {
+        if let Some(session) = session::get(handle) {
+            session.set_sample_rate(if sample_rate > 0 {
+                Some(sample_rate as u32)
+            } else {
+                None
+            });
+        }
+    }
+
+    // Non-positive values restore the source's default buffer size.
+    #[no_mangle]
+    pub extern "C" fn Java_tw_mapacode_androidsink_AndroidSink_nativeSetBufSize(
+        _env: JNIEnv,
+        _: JClass,
+        handle: jlong,
+        buf_size: jint,
+    ) {
+        if let Some(session) = session::get(handle) {
+            session.set_samples_per_buffer(if buf_size > 0 {
+                Some(buf_size as u32)
+            } else {
+                None
+            });
+        }
+    }
 
+    // Non-positive values accept any channel count.
     #[no_mangle]
-    pub unsafe extern "C" fn Java_tw_mapacode_androidsink_AndroidSink_nativeRun(
+    pub extern "C" fn Java_tw_mapacode_androidsink_AndroidSink_nativeSetChannels(
         _env: JNIEnv,
         _: JClass,
+        handle: jlong,
+        channels: jint,
     ) {
-        if !RUNNING {
-            RUNNING = true;
-            gst_trace!(CAT, "running");
-            std::thread::spawn(move || {
-                super::run();
-                gst_trace!(CAT, "stopped running");
-                RUNNING = false;
+        if let Some(session) = session::get(handle) {
+            session.set_channels(if channels > 0 {
+                Some(channels as u32)
+            } else {
+                None
             });
         }
     }
 
+    // A null directory disables recording, non-positive limits are ignored.
+    #[no_mangle]
+    pub extern "C" fn Java_tw_mapacode_androidsink_AndroidSink_nativeSetRecording(
+        env: JNIEnv,
+        _: JClass,
+        handle: jlong,
+        directory: JString,
+        flac: jboolean,
+        max_duration_ms: jlong,
+        max_size: jlong,
+    ) {
+        let session = match session::get(handle) {
+            Some(session) => session,
+            None => return,
+        };
+        if directory.is_null() {
+            session.set_record(None);
+            return;
+        }
+
+        let directory: String = match env.get_string(directory) {
+            Ok(directory) => directory.into(),
+            Err(e) => {
+                gst_warning!(CAT, "could not read recording directory: {}", e);
+                return;
+            }
+        };
+        let format = if flac != 0 {
+            crate::record::RecordFormat::Flac
+        } else {
+            crate::record::RecordFormat::Wav
+        };
+        let mut record = crate::record::RecordConfig::new(directory, format);
+        if max_duration_ms > 0 {
+            record.max_duration = Some(std::time::Duration::from_millis(max_duration_ms as u64));
+        }
+        if max_size > 0 {
+            record.max_size = Some(max_size as u64);
+        }
+        session.set_record(Some(record));
+    }
+
+    #[no_mangle]
+    pub extern "C" fn Java_tw_mapacode_androidsink_AndroidSink_nativeRun(
+        _env: JNIEnv,
+        _: JClass,
+        handle: jlong,
+    ) {
+        if let Some(session) = session::get(handle) {
+            session.run();
+        }
+    }
+
+    #[no_mangle]
+    pub extern "C" fn Java_tw_mapacode_androidsink_AndroidSink_nativeStop(
+        _env: JNIEnv,
+        _: JClass,
+        handle: jlong,
+    ) {
+        if let Some(session) = session::get(handle) {
+            session.stop();
+        }
+    }
+
+    #[no_mangle]
+    pub extern "C" fn Java_tw_mapacode_androidsink_AndroidSink_nativePause(
+        _env: JNIEnv,
+        _: JClass,
+        handle: jlong,
+    ) {
+        if let Some(session) = session::get(handle) {
+            session.pause();
+        }
+    }
+
+    #[no_mangle]
+    pub extern "C" fn Java_tw_mapacode_androidsink_AndroidSink_nativeResume(
+        _env: JNIEnv,
+        _: JClass,
+        handle: jlong,
+    ) {
+        if let Some(session) = session::get(handle) {
+            session.resume();
+        }
+    }
+
     #[no_mangle]
     unsafe fn JNI_OnLoad(jvm: JavaVM, _reserved: *mut c_void) -> jint {
-        let mut plugins_core = vec![
-            "coreelements",
-            "coretracers",
-            "adder",
-            "app",
-            "audioconvert",
-            "audiomixer",
-            "audiorate",
-            "audioresample",
-            "audiotestsrc",
-            "compositor",
-            "gio",
-            "overlaycomposition",
-            "pango",
-            "rawparse",
-            "typefindfunctions",
-            "videoconvert",
-            "videorate",
-            "videoscale",
-            "videotestsrc",
-            "volume",
-            "autodetect",
-            "videofilter",
-        ];
-        let mut plugins_codecs = vec!["androidmedia"];
-        let mut plugin_names = Vec::new();
-        plugin_names.append(&mut plugins_core);
-        plugin_names.append(&mut plugins_codecs);
-
-        gstinit::on_load(jvm, _reserved, plugin_names)
+        gstinit::on_load(jvm, _reserved)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blamed_fragment_names_element() {
+        let description = "audiotestsrc freq=440 ! audiomixer name=mix ! audio/x-raw,rate=8000 \
+             ! audioconvert audiotestsrc wave=silence ! mix. \
+             textoverlay text=\"no fakesink here\" ! fakesink";
+        let blamed = |message| blamed_fragment(description, message);
+        assert_eq!(
+            blamed(r#"no property "foo" in element "mix""#),
+            Some("audiomixer name=mix")
+        );
+        assert_eq!(
+            blamed(r#"could not set property "volume" in element "audioconvert3""#),
+            Some("audioconvert audiotestsrc wave=silence")
+        );
+        // Quoted values create no element.
+        assert_eq!(blamed("could not link fakesink12"), Some("fakesink"));
+
+        // Either source, or both ends of a link, could be meant.
+        assert_eq!(
+            blamed(r#"no property "foo" in element "audiotestsrc0""#),
+            None
+        );
+        assert_eq!(blamed("could not link audioconvert0 to mix"), None);
+        // An element given a name is not known by its factory's any more.
+        assert_eq!(
+            blamed(r#"no property "foo" in element "audiomixer0""#),
+            None
+        );
+        assert_eq!(blamed("syntax error"), None);
     }
 }
@@ -0,0 +1,362 @@
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+use anyhow::Error;
+use gst_audio::AudioChannelPosition;
+
+use crate::{SampleConsumer, Samples};
+
+// Gating thresholds from ITU-R BS.1770-4 and EBU Tech 3342.
+const ABSOLUTE_GATE: f64 = -70.0;
+const RELATIVE_GATE: f64 = -10.0;
+const LRA_RELATIVE_GATE: f64 = -20.0;
+
+// Number of 100 ms blocks in the momentary and short-term windows.
+const MOMENTARY_BLOCKS: usize = 4;
+const SHORT_TERM_BLOCKS: usize = 30;
+
+// Loudness in LUFS of a mean square energy.
+fn to_lufs(energy: f64) -> f64 {
+    -0.691 + 10.0 * energy.log10()
+}
+
+fn to_energy(lufs: f64) -> f64 {
+    10f64.powf((lufs + 0.691) / 10.0)
+}
+
+/// Weight of a channel in the loudness sum: surround channels count +1.5 dB and LFE
+/// channels are ignored.
+pub fn channel_weight(position: AudioChannelPosition) -> f64 {
+    match position {
+        AudioChannelPosition::Lfe1 | AudioChannelPosition::Lfe2 => 0.0,
+        AudioChannelPosition::RearLeft
+        | AudioChannelPosition::RearRight
+        | AudioChannelPosition::SideLeft
+        | AudioChannelPosition::SideRight
+        | AudioChannelPosition::SurroundLeft
+        | AudioChannelPosition::SurroundRight => 1.41,
+        _ => 1.0,
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 3],
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct BiquadState {
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&self, state: &mut BiquadState, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * state.x[0] + self.b[2] * state.x[1]
+            - self.a[1] * state.y[0]
+            - self.a[2] * state.y[1];
+        state.x = [x, state.x[0]];
+        state.y = [y, state.y[0]];
+        y
+    }
+}
+
+// K-weighting filter for `rate`: a high shelf modelling the head followed by the
+// RLB high pass. The analog prototypes are mapped to the rate with the bilinear
+// transform, giving the coefficients tabulated in BS.1770 at 48 kHz.
+fn k_weighting(rate: u32) -> [Biquad; 2] {
+    let rate = f64::from(rate);
+
+    let k = (PI * 1_681.974_450_955_533 / rate).tan();
+    let q = 0.707_175_236_955_419_6;
+    let vh = 10f64.powf(3.999_843_853_973_347 / 20.0);
+    let vb = vh.powf(0.499_666_774_154_541_6);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    };
+
+    let k = (PI * 38.135_470_876_024_44 / rate).tan();
+    let q = 0.500_327_037_323_877_3;
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    };
+
+    [shelf, high_pass]
+}
+
+/// Loudness meter following EBU R128 / ITU-R BS.1770: momentary (400 ms), short-term
+/// (3 s) and gated integrated loudness in LUFS, and loudness range in LU.
+#[derive(Debug)]
+pub struct LoudnessMeter {
+    rate: u32,
+    filters: [Biquad; 2],
+    states: Vec<[BiquadState; 2]>,
+    // Frames per 100 ms block and progress in the current block.
+    block_frames: usize,
+    block_pos: usize,
+    block_sum: f64,
+    // Energies of the last `SHORT_TERM_BLOCKS` 100 ms blocks, newest last.
+    blocks: VecDeque<f64>,
+    // Energies of every 400 ms gating block, overlapping by 75%.
+    gating_blocks: Vec<f64>,
+    // Energies of every 3 s window, one per 100 ms block.
+    short_term_blocks: Vec<f64>,
+}
+
+impl Default for LoudnessMeter {
+    fn default() -> Self {
+        LoudnessMeter::new()
+    }
+}
+
+impl LoudnessMeter {
+    pub fn new() -> Self {
+        LoudnessMeter {
+            rate: 0,
+            filters: k_weighting(48_000),
+            states: Vec::new(),
+            block_frames: 0,
+            block_pos: 0,
+            block_sum: 0.0,
+            blocks: VecDeque::with_capacity(SHORT_TERM_BLOCKS),
+            gating_blocks: Vec::new(),
+            short_term_blocks: Vec::new(),
+        }
+    }
+
+    /// Forget everything measured so far.
+    pub fn reset(&mut self) {
+        *self = LoudnessMeter::new();
+    }
+
+    /// Measure interleaved `samples` at `rate` Hz, with one entry of `weights` per
+    /// channel (see `channel_weight`). The measurement restarts when the rate or the
+    /// channel count changes.
+    pub fn process(&mut self, samples: &[f32], weights: &[f64], rate: u32) {
+        let channels = weights.len();
+        if rate != self.rate || channels != self.states.len() {
+            self.reset();
+            self.rate = rate;
+            self.filters = k_weighting(rate);
+            self.states = vec![[BiquadState::default(); 2]; channels];
+            self.block_frames = ((rate as usize) / 10).max(1);
+        }
+
+        for frame in samples.chunks_exact(channels) {
+            for ((sample, state), weight) in frame.iter().zip(self.states.iter_mut()).zip(weights) {
+                let y = self.filters[0].process(&mut state[0], f64::from(*sample));
+                let y = self.filters[1].process(&mut state[1], y);
+                self.block_sum += weight * y * y;
+            }
+
+            self.block_pos += 1;
+            if self.block_pos == self.block_frames {
+                self.push_block(self.block_sum / self.block_frames as f64);
+                self.block_pos = 0;
+                self.block_sum = 0.0;
+            }
+        }
+    }
+
+    fn push_block(&mut self, energy: f64) {
+        if self.blocks.len() == SHORT_TERM_BLOCKS {
+            self.blocks.pop_front();
+        }
+        self.blocks.push_back(energy);
+
+        if let Some(energy) = self.window_energy(MOMENTARY_BLOCKS) {
+            self.gating_blocks.push(energy);
+        }
+        if let Some(energy) = self.window_energy(SHORT_TERM_BLOCKS) {
+            self.short_term_blocks.push(energy);
+        }
+    }
+
+    // Mean energy of the last `len` 100 ms blocks, once that many were measured.
+    fn window_energy(&self, len: usize) -> Option<f64> {
+        if self.blocks.len() < len {
+            return None;
+        }
+        Some(self.blocks.iter().rev().take(len).sum::<f64>() / len as f64)
+    }
+
+    /// Loudness of the last 400 ms in LUFS.
+    pub fn momentary(&self) -> Option<f64> {
+        self.window_energy(MOMENTARY_BLOCKS).map(to_lufs)
+    }
+
+    /// Loudness of the last 3 s in LUFS.
+    pub fn short_term(&self) -> Option<f64> {
+        self.window_energy(SHORT_TERM_BLOCKS).map(to_lufs)
+    }
+
+    /// Gated loudness of everything measured so far in LUFS.
+    pub fn integrated(&self) -> Option<f64> {
+        let absolute_gate = to_energy(ABSOLUTE_GATE);
+        let gated: Vec<f64> = self
+            .gating_blocks
+            .iter()
+            .copied()
+            .filter(|energy| *energy > absolute_gate)
+            .collect();
+        if gated.is_empty() {
+            return None;
+        }
+
+        let mean = gated.iter().sum::<f64>() / gated.len() as f64;
+        let relative_gate = to_energy(to_lufs(mean) + RELATIVE_GATE);
+        let gated: Vec<f64> = gated
+            .into_iter()
+            .filter(|energy| *energy > relative_gate)
+            .collect();
+        if gated.is_empty() {
+            return None;
+        }
+
+        Some(to_lufs(gated.iter().sum::<f64>() / gated.len() as f64))
+    }
+
+    /// Loudness range (LRA) of everything measured so far in LU: the spread between
+    /// the 10th and 95th percentiles of the gated short-term loudness.
+    pub fn loudness_range(&self) -> Option<f64> {
+        let absolute_gate = to_energy(ABSOLUTE_GATE);
+        let gated: Vec<f64> = self
+            .short_term_blocks
+            .iter()
+            .copied()
+            .filter(|energy| *energy > absolute_gate)
+            .collect();
+        if gated.is_empty() {
+            return None;
+        }
+
+        let mean = gated.iter().sum::<f64>() / gated.len() as f64;
+        let relative_gate = to_energy(to_lufs(mean) + LRA_RELATIVE_GATE);
+        let mut loudness: Vec<f64> = gated
+            .into_iter()
+            .filter(|energy| *energy > relative_gate)
+            .map(to_lufs)
+            .collect();
+        if loudness.is_empty() {
+            return None;
+        }
+
+        loudness.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let percentile = |p: f64| loudness[((loudness.len() - 1) as f64 * p).round() as usize];
+        Some(percentile(0.95) - percentile(0.10))
+    }
+}
+
+impl SampleConsumer for LoudnessMeter {
+    fn consume(&mut self, samples: &Samples) -> Result<(), Error> {
+        let weights: Vec<f64> = match samples.info.positions() {
+            Some(positions) => positions.iter().map(|p| channel_weight(*p)).collect(),
+            None => vec![1.0; samples.channels()],
+        };
+        self.process(samples.data, &weights, samples.info.rate());
+
+        // Values not measured yet are reported as not a number.
+        let value = |value: Option<f64>| value.unwrap_or(f64::NAN);
+        samples.report(
+            samples
+                .metric("loudness")
+                .with_value("momentary", value(self.momentary()))
+                .with_value("short_term", value(self.short_term()))
+                .with_value("integrated", value(self.integrated()))
+                .with_value("range", value(self.loudness_range())),
+        );
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 48_000;
+
+    // Feed `meter` with `seconds` of a stereo sine of `frequency` Hz whose peak is at
+    // `level_db` dBFS on both channels, in buffers of 1024 frames.
+    fn sine(meter: &mut LoudnessMeter, frequency: f64, level_db: f64, seconds: f64) {
+        let amplitude = 10f64.powf(level_db / 20.0);
+        let frames = (seconds * f64::from(RATE)) as usize;
+        let mut samples = Vec::with_capacity(2 * 1024);
+        for start in (0..frames).step_by(1024) {
+            samples.clear();
+            for n in start..(start + 1024).min(frames) {
+                let t = n as f64 / f64::from(RATE);
+                let value = (amplitude * (2.0 * PI * frequency * t).sin()) as f32;
+                samples.extend_from_slice(&[value, value]);
+            }
+            meter.process(&samples, &[1.0, 1.0], RATE);
+        }
+    }
+
+    fn assert_near(value: Option<f64>, expected: f64, tolerance: f64) {
+        let value = value.expect("no measurement");
+        assert!(
+            (value - expected).abs() <= tolerance,
+            "{} instead of {} ± {}",
+            value,
+            expected,
+            tolerance
+        );
+    }
+
+    // EBU Tech 3341, test 1.
+    #[test]
+    fn steady_sine() {
+        let mut meter = LoudnessMeter::new();
+        sine(&mut meter, 997.0, -23.0, 20.0);
+        assert_near(meter.momentary(), -23.0, 0.1);
+        assert_near(meter.short_term(), -23.0, 0.1);
+        assert_near(meter.integrated(), -23.0, 0.1);
+    }
+
+    // EBU Tech 3341, test 3: the quiet parts fall below the relative gate.
+    #[test]
+    fn relative_gate() {
+        let mut meter = LoudnessMeter::new();
+        sine(&mut meter, 997.0, -36.0, 10.0);
+        sine(&mut meter, 997.0, -23.0, 60.0);
+        sine(&mut meter, 997.0, -36.0, 10.0);
+        assert_near(meter.integrated(), -23.0, 0.1);
+    }
+
+    // EBU Tech 3342, tests 1 to 3.
+    #[test]
+    fn loudness_range() {
+        for &(first, second, range) in &[
+            (-20.0, -30.0, 10.0),
+            (-20.0, -15.0, 5.0),
+            (-40.0, -20.0, 20.0),
+        ] {
+            let mut meter = LoudnessMeter::new();
+            sine(&mut meter, 997.0, first, 20.0);
+            sine(&mut meter, 997.0, second, 20.0);
+            assert_near(meter.loudness_range(), range, 1.0);
+        }
+    }
+
+    #[test]
+    fn nothing_measured() {
+        let mut meter = LoudnessMeter::new();
+        assert_eq!(meter.momentary(), None);
+        assert_eq!(meter.integrated(), None);
+        sine(&mut meter, 997.0, -23.0, 0.3);
+        assert_eq!(meter.momentary(), None);
+        sine(&mut meter, 997.0, -23.0, 0.1);
+        assert!(meter.momentary().is_some());
+        assert_eq!(meter.short_term(), None);
+    }
+}
@@ -0,0 +1,308 @@
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{LineWriter, Write};
+use std::path::Path;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Error;
+
+use crate::CAT;
+
+/// Value of a metric.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Number(f64),
+    Text(String),
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Number(value)
+    }
+}
+
+impl From<u64> for Value {
+    fn from(value: u64) -> Self {
+        Value::Number(value as f64)
+    }
+}
+
+/// Durations are given in seconds, not a number when unknown.
+impl From<gst::ClockTime> for Value {
+    fn from(value: gst::ClockTime) -> Self {
+        match value.nseconds() {
+            Some(nseconds) => Value::Number(nseconds as f64 / 1_000_000_000.0),
+            None => Value::Number(f64::NAN),
+        }
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::Text(value)
+    }
+}
+
+impl From<char> for Value {
+    fn from(value: char) -> Self {
+        Value::Text(value.to_string())
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Number(value) => write!(f, "{}", value),
+            Value::Text(value) => f.write_str(value),
+        }
+    }
+}
+
+/// Measurement made by an analyser.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Metric {
+    /// Time the measurement applies to, on the timeline of the buffer timestamps.
+    pub timestamp: gst::ClockTime,
+    /// Stream id of the analysed stream.
+    pub stream_id: String,
+    /// What was measured, e.g. `level` or `pitch`.
+    pub name: String,
+    /// Channel measured, `None` when the measurement covers all of them.
+    pub channel: Option<String>,
+    /// Named values, in the order the analyser gave them.
+    pub values: Vec<(String, Value)>,
+}
+
+impl Metric {
+    pub fn new(timestamp: gst::ClockTime, stream_id: &str, name: &str) -> Self {
+        Metric {
+            timestamp,
+            stream_id: String::from(stream_id),
+            name: String::from(name),
+            channel: None,
+            values: Vec::new(),
+        }
+    }
+
+    pub fn with_timestamp(mut self, timestamp: gst::ClockTime) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    pub fn with_channel(mut self, channel: String) -> Self {
+        self.channel = Some(channel);
+        self
+    }
+
+    pub fn with_value<V: Into<Value>>(mut self, name: &str, value: V) -> Self {
+        self.values.push((String::from(name), value.into()));
+        self
+    }
+
+    /// Value named `name`, if the metric has one.
+    pub fn value(&self, name: &str) -> Option<&Value> {
+        self.values
+            .iter()
+            .find(|(value_name, _)| value_name == name)
+            .map(|(_, value)| value)
+    }
+
+    /// Serialise as a single line JSON object, without the line break. The timestamp is
+    /// in nanoseconds and numbers which are not finite are written as `null`.
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("{\"timestamp\":");
+        match self.timestamp.nseconds() {
+            Some(timestamp) => json.push_str(&timestamp.to_string()),
+            None => json.push_str("null"),
+        }
+        json.push_str(",\"stream_id\":");
+        push_json_string(&mut json, &self.stream_id);
+        json.push_str(",\"name\":");
+        push_json_string(&mut json, &self.name);
+        json.push_str(",\"channel\":");
+        match self.channel {
+            Some(ref channel) => push_json_string(&mut json, channel),
+            None => json.push_str("null"),
+        }
+        json.push_str(",\"values\":{");
+        for (index, (name, value)) in self.values.iter().enumerate() {
+            if index > 0 {
+                json.push(',');
+            }
+            push_json_string(&mut json, name);
+            json.push(':');
+            match value {
+                Value::Number(value) if value.is_finite() => json.push_str(&value.to_string()),
+                Value::Number(_) => json.push_str("null"),
+                Value::Text(value) => push_json_string(&mut json, value),
+            }
+        }
+        json.push_str("}}");
+        json
+    }
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.name)?;
+        if let Some(ref channel) = self.channel {
+            write!(f, " {}", channel)?;
+        }
+        f.write_str(":")?;
+        for (index, (name, value)) in self.values.iter().enumerate() {
+            let separator = if index > 0 { "," } else { "" };
+            write!(f, "{} {} {}", separator, name, value)?;
+        }
+        Ok(())
+    }
+}
+
+// Append `value` as a quoted JSON string.
+pub(crate) fn push_json_string(json: &mut String, value: &str) {
+    json.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
+
+/// Destination of the metrics produced by the analysers of a pipeline.
+///
+/// The default reporter prints every metric with `g_print`. `channel` gives a reporter
+/// delivering them to a receiver instead, and `with_json_lines` adds a file to which
+/// they are appended one JSON object per line.
+#[derive(Clone, Debug)]
+pub struct Reporter {
+    sender: Option<mpsc::Sender<Metric>>,
+    json_lines: Option<Arc<Mutex<LineWriter<std::fs::File>>>>,
+    print: bool,
+}
+
+impl Default for Reporter {
+    fn default() -> Self {
+        Reporter::new().with_print()
+    }
+}
+
+/// Create a reporter sending every metric to the returned receiver, which can be
+/// iterated from another thread. Metrics are dropped once the receiver is gone.
+pub fn channel() -> (Reporter, mpsc::Receiver<Metric>) {
+    let (sender, receiver) = mpsc::channel();
+    let reporter = Reporter {
+        sender: Some(sender),
+        ..Reporter::new()
+    };
+    (reporter, receiver)
+}
+
+impl Reporter {
+    /// Create a reporter discarding every metric.
+    pub fn new() -> Self {
+        Reporter {
+            sender: None,
+            json_lines: None,
+            print: false,
+        }
+    }
+
+    /// Also print every metric with `g_print`.
+    pub fn with_print(mut self) -> Self {
+        self.print = true;
+        self
+    }
+
+    /// Also append every metric to the file at `path` as a line of JSON.
+    pub fn with_json_lines<P: AsRef<Path>>(mut self, path: P) -> Result<Self, Error> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path.as_ref())?;
+        self.json_lines = Some(Arc::new(Mutex::new(LineWriter::new(file))));
+        Ok(self)
+    }
+
+    pub fn report(&self, metric: Metric) {
+        if self.print {
+            glib::g_print!("{}", metric);
+        }
+        if let Some(ref json_lines) = self.json_lines {
+            let mut file = json_lines.lock().unwrap();
+            if let Err(err) = writeln!(file, "{}", metric.to_json()) {
+                gst_warning!(CAT, "could not write metric: {}", err);
+            }
+        }
+        if let Some(ref sender) = self.sender {
+            // A closed receiver only means nobody is interested any more.
+            let _ = sender.send(metric);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json() {
+        let metric = Metric::new(gst::ClockTime::from_mseconds(1500), "id \"1\"", "level")
+            .with_channel(String::from("FrontLeft"))
+            .with_value("peak_db", -6.5)
+            .with_value("text", String::from("a\\b\n\t\u{1}é"));
+        assert_eq!(
+            metric.to_json(),
+            "{\"timestamp\":1500000000,\"stream_id\":\"id \\\"1\\\"\",\"name\":\"level\",\
+             \"channel\":\"FrontLeft\",\"values\":{\"peak_db\":-6.5,\
+             \"text\":\"a\\\\b\\n\\t\\u0001é\"}}"
+        );
+    }
+
+    #[test]
+    fn json_without_timestamp_nor_finite_numbers() {
+        let metric = Metric::new(gst::ClockTime::none(), "", "loudness")
+            .with_value("momentary", f64::NAN)
+            .with_value("range", f64::INFINITY)
+            .with_value("peak", f64::NEG_INFINITY)
+            .with_value("duration", gst::ClockTime::none());
+        assert_eq!(
+            metric.to_json(),
+            "{\"timestamp\":null,\"stream_id\":\"\",\"name\":\"loudness\",\"channel\":null,\
+             \"values\":{\"momentary\":null,\"range\":null,\"peak\":null,\"duration\":null}}"
+        );
+    }
+
+    #[test]
+    fn channel_delivery() {
+        let metric = |seconds| {
+            Metric::new(gst::ClockTime::from_seconds(seconds), "", "rms").with_value("rms", 0.5)
+        };
+        let (reporter, receiver) = channel();
+        // Analysers report from the streaming thread.
+        let streaming = reporter.clone();
+        std::thread::spawn(move || {
+            streaming.report(metric(0));
+            streaming.report(metric(1));
+        })
+        .join()
+        .unwrap();
+        reporter.report(metric(2));
+        drop(reporter);
+
+        // The receiver ends once every reporter is gone.
+        let received = receiver.iter().collect::<Vec<_>>();
+        assert_eq!(received, [metric(0), metric(1), metric(2)]);
+
+        // Reporting after the receiver is gone is not an error.
+        let (reporter, receiver) = channel();
+        drop(receiver);
+        reporter.report(metric(3));
+    }
+}
@@ -0,0 +1,226 @@
+use anyhow::Error;
+
+use crate::consumer::FrameAccumulator;
+use crate::{SampleConsumer, Samples};
+
+/// Pitch estimate of one analysis window.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pitch {
+    /// Buffer timestamp at which the window starts.
+    pub start: gst::ClockTime,
+    /// Fundamental frequency in Hz, `None` when no periodicity was found.
+    pub frequency: Option<f64>,
+    /// Confidence between 0 and 1, one minus the normalised difference at the
+    /// detected period.
+    pub confidence: f64,
+}
+
+/// YIN fundamental frequency estimator over the channels mixed down to mono.
+///
+/// Samples are accumulated across buffers and a pitch is estimated for every window
+/// of `size` samples, advancing by `hop` samples, at most `size`. The lowest
+/// detectable frequency is `2 * rate / size`.
+#[derive(Debug)]
+pub struct PitchDetector {
+    size: usize,
+    hop: usize,
+    threshold: f64,
+    frames: FrameAccumulator,
+    // Cumulative mean normalised difference, reused between windows.
+    difference: Vec<f64>,
+}
+
+impl Default for PitchDetector {
+    /// 2048 sample windows advancing by 1024 samples, with the 0.1 threshold from the
+    /// YIN paper.
+    fn default() -> Self {
+        PitchDetector::new(2048, 1024, 0.1)
+    }
+}
+
+impl PitchDetector {
+    pub fn new(size: usize, hop: usize, threshold: f64) -> Self {
+        assert!(size >= 4 && hop > 0 && hop <= size, "invalid pitch window");
+        PitchDetector {
+            size,
+            hop,
+            threshold,
+            frames: FrameAccumulator::default(),
+            difference: vec![0.0; size / 2],
+        }
+    }
+
+    /// Add interleaved `samples` with `channels` channels at `rate` Hz starting at
+    /// timestamp `pts`, and return the pitch of every window completed by them.
+    pub fn process(
+        &mut self,
+        samples: &[f32],
+        channels: usize,
+        rate: u32,
+        pts: gst::ClockTime,
+    ) -> Vec<Pitch> {
+        self.frames.start_buffer(rate, pts);
+        self.frames.push(samples, channels);
+
+        let mut pitches = Vec::new();
+        while let Some(frame) = self.frames.block(self.size) {
+            let (period, confidence) = estimate(frame, &mut self.difference, self.threshold);
+            pitches.push(Pitch {
+                start: gst::ClockTime::from_nseconds(self.frames.position()),
+                frequency: period.map(|period| f64::from(rate) / period),
+                confidence,
+            });
+            self.frames.advance(self.hop);
+        }
+        pitches
+    }
+}
+
+// Period in samples of a window, if any, and the confidence in it, using `difference`
+// as room for the difference function.
+fn estimate(frame: &[f64], difference: &mut [f64], threshold: f64) -> (Option<f64>, f64) {
+    let max_lag = frame.len() / 2;
+
+    // Difference function, normalised by its cumulative mean.
+    difference[0] = 1.0;
+    let mut sum = 0.0;
+    for lag in 1..max_lag {
+        let d: f64 = frame[..max_lag]
+            .iter()
+            .zip(&frame[lag..lag + max_lag])
+            .map(|(a, b)| (a - b) * (a - b))
+            .sum();
+        sum += d;
+        difference[lag] = if sum > 0.0 { d * lag as f64 / sum } else { 1.0 };
+    }
+
+    // First dip below the threshold, followed down to its minimum.
+    let d = difference;
+    let lag = match (2..max_lag).find(|lag| d[*lag] < threshold) {
+        Some(mut lag) => {
+            while lag + 1 < max_lag && d[lag + 1] < d[lag] {
+                lag += 1;
+            }
+            lag
+        }
+        None => {
+            let best = (2..max_lag)
+                .filter(|lag| !d[*lag].is_nan())
+                .min_by(|a, b| d[*a].total_cmp(&d[*b]))
+                .unwrap_or(1);
+            return (None, (1.0 - d[best]).max(0.0));
+        }
+    };
+
+    // Parabolic interpolation of the minimum between neighbouring lags.
+    let mut period = lag as f64;
+    if lag + 1 < max_lag {
+        let (a, b, c) = (d[lag - 1], d[lag], d[lag + 1]);
+        let denominator = a - 2.0 * b + c;
+        if denominator.abs() > f64::EPSILON {
+            period += 0.5 * (a - c) / denominator;
+        }
+    }
+
+    (Some(period), (1.0 - d[lag]).max(0.0))
+}
+
+impl SampleConsumer for PitchDetector {
+    fn consume(&mut self, samples: &Samples) -> Result<(), Error> {
+        let pitches = self.process(
+            samples.data,
+            samples.channels(),
+            samples.info.rate(),
+            samples.pts,
+        );
+        for pitch in pitches {
+            samples.report(
+                samples
+                    .metric("pitch")
+                    .with_timestamp(pitch.start)
+                    .with_value("frequency", pitch.frequency.unwrap_or(f64::NAN))
+                    .with_value("confidence", pitch.confidence),
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consumer::Collector;
+    use crate::{create_pipeline, Config, PipelineHandle};
+
+    #[test]
+    fn no_pitch_in_nan() {
+        let mut detector = PitchDetector::default();
+        let samples = vec![f32::NAN; 4096];
+        let pitches = detector.process(&samples, 1, 48_000, gst::ClockTime::none());
+        assert!(!pitches.is_empty());
+        for pitch in pitches {
+            assert_eq!(pitch.frequency, None);
+        }
+    }
+
+    #[test]
+    fn window_timestamps() {
+        // Windows of 2048 samples every 1024, the second buffer following the first.
+        let mut detector = PitchDetector::default();
+        let second = gst::ClockTime::from_seconds(1);
+        let mut pitches = detector.process(&[0.0; 3000], 1, 48_000, second);
+        pitches.extend(detector.process(&[0.0; 3000], 1, 48_000, gst::ClockTime::none()));
+        let starts = pitches
+            .iter()
+            .map(|pitch| pitch.start.nseconds().unwrap())
+            .collect::<Vec<_>>();
+        let hop = 1024 * 1_000_000_000 / 48_000;
+        assert_eq!(
+            starts,
+            (0..4)
+                .map(|index| 1_000_000_000 + index * hop)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn detects_audiotestsrc_frequency() {
+        gst::init().unwrap();
+
+        let config = Config {
+            sample_rate: Some(48_000),
+            ..Config::default()
+        };
+        for &freq in &[110.0, 261.63, 440.0, 1000.0, 2500.0] {
+            let mut detector = PitchDetector::default();
+            let (collector, frequencies) = Collector::new(move |samples: &Samples| {
+                let pitches = detector.process(
+                    samples.data,
+                    samples.channels(),
+                    samples.info.rate(),
+                    samples.pts,
+                );
+                pitches
+                    .into_iter()
+                    .filter_map(|pitch| pitch.frequency)
+                    .collect()
+            });
+            let description = format!("audiotestsrc freq={} num-buffers=20", freq);
+            let pipeline = create_pipeline(&description, &config, vec![Box::new(collector)])
+                .expect("audiotestsrc pipeline");
+            PipelineHandle::new(pipeline).run().unwrap();
+
+            let frequencies = frequencies.lock().unwrap();
+            assert!(!frequencies.is_empty(), "no pitch found for {} Hz", freq);
+            for frequency in frequencies.iter() {
+                assert!(
+                    (frequency - freq).abs() / freq < 0.005,
+                    "detected {} Hz for {} Hz",
+                    frequency,
+                    freq
+                );
+            }
+        }
+    }
+}
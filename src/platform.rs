@@ -0,0 +1,654 @@
+//! Services of the platform GStreamer is initialised on, and the initialisation
+//! sequence built on them.
+//!
+//! On Android, `nativeInit` provides logcat, the directories of the app's `Context` and
+//! `dlopen`. Keeping them behind traits lets the sequence run against mocks on a host.
+
+use std::ffi::CStr;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::Error;
+use dlopen::symbor::Library;
+use glib::{ObjectExt, ObjectType};
+use gst::{ClockTime, DebugCategory, DebugLevel, DebugMessage, Pad};
+
+use crate::plugins::{InitReport, PluginManifest, PluginReport, PluginStatus};
+
+/// Tag of the messages logged while initialising.
+pub const INIT_TAG: &str = "GStreamer+androidinit";
+
+/// Priority of a log message, from the most to the least verbose.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogPriority {
+    Verbose,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl From<glib::LogLevel> for LogPriority {
+    fn from(level: glib::LogLevel) -> Self {
+        match level {
+            glib::LogLevel::Error | glib::LogLevel::Critical => LogPriority::Error,
+            glib::LogLevel::Warning => LogPriority::Warn,
+            glib::LogLevel::Message | glib::LogLevel::Info => LogPriority::Info,
+            glib::LogLevel::Debug => LogPriority::Debug,
+        }
+    }
+}
+
+impl From<DebugLevel> for LogPriority {
+    fn from(level: DebugLevel) -> Self {
+        match level {
+            DebugLevel::Error => LogPriority::Error,
+            DebugLevel::Warning => LogPriority::Warn,
+            DebugLevel::Info => LogPriority::Info,
+            DebugLevel::Debug => LogPriority::Debug,
+            _ => LogPriority::Verbose,
+        }
+    }
+}
+
+/// Destination of the GLib and GStreamer logs, e.g. logcat.
+pub trait LogSink: Send + Sync {
+    fn write(&self, priority: LogPriority, tag: &str, message: &str);
+}
+
+/// Directories private to the app.
+pub trait AppDirectories {
+    /// Directory for files which can be deleted at any time.
+    fn cache_dir(&self) -> Result<PathBuf, Error>;
+    /// Directory for persistent files.
+    fn files_dir(&self) -> Result<PathBuf, Error>;
+}
+
+/// Loads the shared libraries of the plugins.
+pub trait LibraryLoader {
+    /// Kept loaded for as long as it is alive.
+    type Library;
+
+    fn open(&self, file_name: &str) -> Result<Self::Library, Error>;
+
+    /// Call the function `symbol` of `library`.
+    ///
+    /// # Safety
+    ///
+    /// `symbol` must be a C function without arguments nor return value.
+    unsafe fn call(&self, library: &Self::Library, symbol: &str) -> Result<(), Error>;
+
+    /// Whether GStreamer knows the plugin `name`, once its register function was called.
+    fn registered(&self, name: &str) -> bool;
+}
+
+/// Loader opening libraries with `dlopen`, from the library search path.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DlopenLoader;
+
+impl LibraryLoader for DlopenLoader {
+    type Library = Library;
+
+    fn open(&self, file_name: &str) -> Result<Library, Error> {
+        Ok(Library::open(file_name)?)
+    }
+
+    unsafe fn call(&self, library: &Library, symbol: &str) -> Result<(), Error> {
+        let function = library.symbol::<unsafe extern "C" fn()>(symbol)?;
+        function();
+        Ok(())
+    }
+
+    fn registered(&self, name: &str) -> bool {
+        gst::Registry::get().find_plugin(name).is_some()
+    }
+}
+
+/// Process wide state changed by the initialisation.
+pub trait Process {
+    fn set_var(&self, name: &str, value: &Path);
+    /// See `redirect_logs`.
+    fn redirect_logs(&self, log: Arc<dyn LogSink>);
+    fn init_gstreamer(&self) -> Result<(), Error>;
+}
+
+/// The process this code runs in.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CurrentProcess;
+
+impl Process for CurrentProcess {
+    fn set_var(&self, name: &str, value: &Path) {
+        // Not thread-safe on some platforms, nothing else should be running yet.
+        std::env::set_var(name, value);
+    }
+
+    fn redirect_logs(&self, log: Arc<dyn LogSink>) {
+        redirect_logs(log);
+    }
+
+    fn init_gstreamer(&self) -> Result<(), Error> {
+        gst::init().map_err(|e| anyhow::anyhow!("GStreamer initialization failed: {}", e))
+    }
+}
+
+/// Environment variables pointing GLib and GStreamer at the app's directories, in the
+/// order they are set.
+pub fn environment(cache_dir: &Path, files_dir: &Path) -> Vec<(&'static str, PathBuf)> {
+    let mut environment = Vec::new();
+    for name in &["TMP", "TEMP", "TMPDIR", "XDG_RUNTIME_DIR", "XDG_CACHE_HOME"] {
+        environment.push((*name, cache_dir.to_path_buf()));
+    }
+    environment.push(("GST_REGISTRY", cache_dir.join("registry.bin")));
+    environment.push(("GST_REGISTRY_REUSE_PLUGIN_SCANNER", PathBuf::from("no")));
+    for name in &[
+        "HOME",
+        "XDG_DATA_DIRS",
+        "XDG_CONFIG_DIRS",
+        "XDG_CONFIG_HOME",
+        "XDG_DATA_HOME",
+    ] {
+        environment.push((*name, files_dir.to_path_buf()));
+    }
+    environment.push(("FONTCONFIG_PATH", files_dir.join("fontconfig")));
+    environment.push((
+        "CA_CERTIFICATES",
+        files_dir
+            .join("ssl")
+            .join("certs")
+            .join("ca-certificates.crt"),
+    ));
+    environment
+}
+
+/// File name of the shared library of the plugin `name`, e.g. `libgstcoreelements.so`.
+pub fn plugin_file_name(name: &str) -> String {
+    format!("libgst{}.so", name)
+}
+
+/// Function registering the plugin `name` with GStreamer.
+pub fn plugin_register_symbol(name: &str) -> String {
+    format!("gst_plugin_{}_register", name)
+}
+
+// Name of a GstObject, read without taking its lock as the log function may be called
+// with it held.
+unsafe fn object_name<'a>(object: *const gst_sys::GstObject) -> Option<&'a str> {
+    if object.is_null() || (*object).name.is_null() {
+        None
+    } else {
+        CStr::from_ptr((*object).name).to_str().ok()
+    }
+}
+
+// Format a GStreamer debug message like the default log function and write it to `log`.
+#[allow(clippy::too_many_arguments)]
+fn write_debug_message(
+    log: &dyn LogSink,
+    start: ClockTime,
+    category: DebugCategory,
+    level: DebugLevel,
+    file: &str,
+    function: &str,
+    line: u32,
+    object: Option<&glib::Object>,
+    message: &DebugMessage,
+) {
+    if level > category.get_threshold() {
+        return;
+    }
+
+    let elapsed = gst::util_get_timestamp() - start;
+    let tag = String::from("GStreamer+") + category.get_name();
+    let mut label = String::new();
+    if let Some(obj) = object {
+        let ptr = obj.as_ptr() as *const gst_sys::GstObject;
+        if obj.is::<Pad>() {
+            let (parent_name, pad_name) = unsafe { (object_name((*ptr).parent), object_name(ptr)) };
+            write!(
+                label,
+                ":<{}:{}>",
+                parent_name.unwrap_or(""),
+                pad_name.unwrap_or("")
+            )
+            .unwrap();
+        } else if obj.is::<gst::Object>() {
+            let name = unsafe { object_name(ptr) };
+            write!(label, ":<{}>", name.unwrap_or("")).unwrap();
+        } else {
+            write!(label, ":<{}@{:#x?}>", obj.get_type(), obj).unwrap();
+        }
+    }
+    let msg = format!(
+        "{} {:#x?} {}:{}:{}{} {}",
+        elapsed,
+        unsafe { libc::pthread_self() },
+        file,
+        line,
+        function,
+        label,
+        message.get().unwrap_or_default()
+    );
+    log.write(level.into(), &tag, &msg);
+}
+
+/// Send the output of `g_print`, `g_printerr`, the GLib log and the GStreamer debug log
+/// to `log`, instead of stdout and stderr.
+pub fn redirect_logs(log: Arc<dyn LogSink>) {
+    let print = log.clone();
+    glib::set_print_handler(move |msg| print.write(LogPriority::Info, "GLib+stdout", msg));
+    let printerr = log.clone();
+    glib::set_printerr_handler(move |msg| printerr.write(LogPriority::Error, "GLib+stderr", msg));
+    let glib_log = log.clone();
+    glib::log_set_default_handler(move |domain, level, msg| {
+        glib_log.write(level.into(), &(String::from("Glib+") + domain), msg)
+    });
+
+    // Disable this for releases if performance is important
+    // or increase the threshold to get more information
+    gst::debug_set_active(true);
+    gst::debug_set_default_threshold(DebugLevel::Warning);
+    gst::debug_remove_default_log_function();
+    let start = gst::util_get_timestamp();
+    gst::debug_add_log_function(
+        move |category, level, file, function, line, object, message| {
+            write_debug_message(
+                log.as_ref(),
+                start,
+                category,
+                level,
+                file,
+                function,
+                line,
+                object,
+                message,
+            )
+        },
+    );
+}
+
+/// Open the library of every plugin of `manifest` and register it, in order, reporting
+/// how it went for each. Plugins which cannot be registered are logged and skipped.
+///
+/// Returns the libraries opened, which must stay loaded as long as GStreamer is used.
+pub fn load_plugins<L: LibraryLoader>(
+    loader: &L,
+    log: &dyn LogSink,
+    manifest: &PluginManifest,
+) -> (Vec<L::Library>, InitReport) {
+    log.write(LogPriority::Verbose, INIT_TAG, "load plugins");
+    let mut libraries = Vec::new();
+    let mut report = InitReport::default();
+    for plugin in &manifest.plugins {
+        let file_name = plugin_file_name(&plugin.name);
+        log.write(
+            LogPriority::Verbose,
+            INIT_TAG,
+            &format!("loading {}", file_name),
+        );
+        let status = match loader.open(&file_name) {
+            Ok(library) => {
+                log.write(
+                    LogPriority::Verbose,
+                    INIT_TAG,
+                    &format!("registering {}", file_name),
+                );
+                let symbol = plugin_register_symbol(&plugin.name);
+                // Plugin register functions take no arguments and return nothing.
+                let status = match unsafe { loader.call(&library, &symbol) } {
+                    Ok(()) if loader.registered(&plugin.name) => PluginStatus::Loaded,
+                    Ok(()) => PluginStatus::RegistrationFailed,
+                    Err(e) => PluginStatus::MissingSymbol(e.to_string()),
+                };
+                // Kept even when registration failed, GStreamer may hold parts of it.
+                libraries.push(library);
+                status
+            }
+            Err(e) => PluginStatus::MissingLibrary(e.to_string()),
+        };
+        if status != PluginStatus::Loaded {
+            let priority = if plugin.required {
+                LogPriority::Error
+            } else {
+                LogPriority::Warn
+            };
+            log.write(priority, INIT_TAG, &format!("{}: {}", plugin.name, status));
+        }
+        report.plugins.push(PluginReport {
+            plugin: plugin.clone(),
+            status,
+        });
+    }
+    (libraries, report)
+}
+
+/// Initialise GStreamer for an app in `process`: point the environment at the app's
+/// directories, redirect the logs to `log`, then initialise GStreamer and register
+/// `plugins`.
+///
+/// Returns the plugin libraries, which must stay loaded as long as GStreamer is used,
+/// and the report of their registration. Missing plugins are not an error here, see
+/// `InitReport::check`.
+pub fn init<D: AppDirectories, L: LibraryLoader, P: Process>(
+    dirs: &D,
+    loader: &L,
+    process: &P,
+    log: Arc<dyn LogSink>,
+    plugins: &PluginManifest,
+) -> Result<(Vec<L::Library>, InitReport), Error> {
+    let cache_dir = dirs.cache_dir()?;
+    let files_dir = dirs.files_dir()?;
+    log.write(
+        LogPriority::Verbose,
+        INIT_TAG,
+        &format!("cache_dir: {:?}, files_dir: {:?}", cache_dir, files_dir),
+    );
+    for (name, value) in environment(&cache_dir, &files_dir) {
+        log.write(
+            LogPriority::Verbose,
+            INIT_TAG,
+            &format!("{}: {:?}", name, value),
+        );
+        process.set_var(name, &value);
+    }
+
+    log.write(LogPriority::Verbose, INIT_TAG, "set glib handlers");
+    process.redirect_logs(log.clone());
+
+    log.write(LogPriority::Verbose, INIT_TAG, "gst init");
+    process.init_gstreamer()?;
+
+    Ok(load_plugins(loader, log.as_ref(), plugins))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    // Records every call made by the initialisation, in order.
+    #[derive(Default)]
+    struct Journal(Mutex<Vec<String>>);
+
+    impl Journal {
+        fn push(&self, entry: String) {
+            self.0.lock().unwrap().push(entry);
+        }
+
+        fn entries(&self) -> Vec<String> {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    impl LogSink for Journal {
+        fn write(&self, priority: LogPriority, tag: &str, message: &str) {
+            if tag == INIT_TAG {
+                self.push(format!("log {:?} {}", priority, message));
+            }
+        }
+    }
+
+    struct MockDirectories(PathBuf, Arc<Journal>);
+
+    impl AppDirectories for MockDirectories {
+        fn cache_dir(&self) -> Result<PathBuf, Error> {
+            self.1.push(String::from("cache_dir"));
+            Ok(self.0.join("cache"))
+        }
+
+        fn files_dir(&self) -> Result<PathBuf, Error> {
+            self.1.push(String::from("files_dir"));
+            Ok(self.0.join("files"))
+        }
+    }
+
+    // Knows every library but those in `missing`, whose symbols are all found but those
+    // of the `no_symbol` libraries. Plugins in `unregistered` fail to register.
+    struct MockLoader {
+        journal: Arc<Journal>,
+        missing: Vec<&'static str>,
+        no_symbol: Vec<&'static str>,
+        unregistered: Vec<&'static str>,
+    }
+
+    impl MockLoader {
+        fn new(journal: Arc<Journal>) -> Self {
+            MockLoader {
+                journal,
+                missing: Vec::new(),
+                no_symbol: Vec::new(),
+                unregistered: Vec::new(),
+            }
+        }
+    }
+
+    impl LibraryLoader for MockLoader {
+        type Library = String;
+
+        fn open(&self, file_name: &str) -> Result<String, Error> {
+            self.journal.push(format!("open {}", file_name));
+            if self.missing.contains(&file_name) {
+                anyhow::bail!("{}: not found", file_name);
+            }
+            Ok(String::from(file_name))
+        }
+
+        unsafe fn call(&self, library: &String, symbol: &str) -> Result<(), Error> {
+            self.journal.push(format!("call {} {}", library, symbol));
+            if self.no_symbol.contains(&library.as_str()) {
+                anyhow::bail!("{}: undefined symbol {}", library, symbol);
+            }
+            Ok(())
+        }
+
+        fn registered(&self, name: &str) -> bool {
+            !self.unregistered.contains(&name)
+        }
+    }
+
+    // Records the changes instead of making them.
+    struct MockProcess(Arc<Journal>);
+
+    impl Process for MockProcess {
+        fn set_var(&self, name: &str, value: &Path) {
+            self.0.push(format!("set {} {}", name, value.display()));
+        }
+
+        fn redirect_logs(&self, _log: Arc<dyn LogSink>) {
+            self.0.push(String::from("redirect logs"));
+        }
+
+        fn init_gstreamer(&self) -> Result<(), Error> {
+            self.0.push(String::from("gst init"));
+            Ok(())
+        }
+    }
+
+    fn calls(journal: &Journal) -> Vec<String> {
+        journal
+            .entries()
+            .into_iter()
+            .filter(|entry| !entry.starts_with("log "))
+            .collect()
+    }
+
+    #[test]
+    fn environment_layout() {
+        let env = environment(Path::new("/data/cache"), Path::new("/data/files"));
+        let names = env.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "TMP",
+                "TEMP",
+                "TMPDIR",
+                "XDG_RUNTIME_DIR",
+                "XDG_CACHE_HOME",
+                "GST_REGISTRY",
+                "GST_REGISTRY_REUSE_PLUGIN_SCANNER",
+                "HOME",
+                "XDG_DATA_DIRS",
+                "XDG_CONFIG_DIRS",
+                "XDG_CONFIG_HOME",
+                "XDG_DATA_HOME",
+                "FONTCONFIG_PATH",
+                "CA_CERTIFICATES",
+            ]
+        );
+        let value = |name: &str| env.iter().find(|(n, _)| *n == name).unwrap().1.clone();
+        assert_eq!(value("TMPDIR"), PathBuf::from("/data/cache"));
+        assert_eq!(
+            value("GST_REGISTRY"),
+            PathBuf::from("/data/cache/registry.bin")
+        );
+        assert_eq!(
+            value("GST_REGISTRY_REUSE_PLUGIN_SCANNER"),
+            PathBuf::from("no")
+        );
+        assert_eq!(value("HOME"), PathBuf::from("/data/files"));
+        assert_eq!(
+            value("FONTCONFIG_PATH"),
+            PathBuf::from("/data/files/fontconfig")
+        );
+        assert_eq!(
+            value("CA_CERTIFICATES"),
+            PathBuf::from("/data/files/ssl/certs/ca-certificates.crt")
+        );
+    }
+
+    #[test]
+    fn plugins_registered_in_order() {
+        let journal = Arc::new(Journal::default());
+        let mut loader = MockLoader::new(journal.clone());
+        loader.missing.push("libgstaudiomixer.so");
+        loader.no_symbol.push("libgstapp.so");
+        loader.unregistered.push("volume");
+
+        let (plugins, report) = load_plugins(
+            &loader,
+            journal.as_ref(),
+            &PluginManifest::new(&["coreelements", "audiomixer", "app"], &["volume"]),
+        );
+
+        assert_eq!(
+            calls(&journal),
+            [
+                "open libgstcoreelements.so",
+                "call libgstcoreelements.so gst_plugin_coreelements_register",
+                "open libgstaudiomixer.so",
+                "open libgstapp.so",
+                "call libgstapp.so gst_plugin_app_register",
+                "open libgstvolume.so",
+                "call libgstvolume.so gst_plugin_volume_register",
+            ]
+        );
+        // Libraries stay loaded even when their plugin could not be registered.
+        assert_eq!(
+            plugins,
+            ["libgstcoreelements.so", "libgstapp.so", "libgstvolume.so"]
+        );
+
+        let statuses = report
+            .plugins
+            .iter()
+            .map(|report| (report.plugin.name.as_str(), report.status.name()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            statuses,
+            [
+                ("coreelements", "loaded"),
+                ("audiomixer", "missing_library"),
+                ("app", "missing_symbol"),
+                ("volume", "registration_failed"),
+            ]
+        );
+        assert_eq!(
+            report.plugins[2].status.error(),
+            Some("libgstapp.so: undefined symbol gst_plugin_app_register")
+        );
+        let missing = report
+            .missing_required()
+            .map(|report| report.plugin.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(missing, ["audiomixer", "app"]);
+        assert!(report.check().is_err());
+
+        // Only required plugins are logged as errors.
+        let logged = |prefix: &str| {
+            journal
+                .entries()
+                .into_iter()
+                .filter(|entry| entry.starts_with(prefix))
+                .count()
+        };
+        assert_eq!(logged("log Error"), 2);
+        assert_eq!(logged("log Warn volume: registration_failed"), 1);
+    }
+
+    #[test]
+    fn init_sequence() {
+        let journal = Arc::new(Journal::default());
+        let dirs = MockDirectories(PathBuf::from("/data"), journal.clone());
+        let loader = MockLoader::new(journal.clone());
+        let process = MockProcess(journal.clone());
+
+        let (plugins, report) = init(
+            &dirs,
+            &loader,
+            &process,
+            journal.clone(),
+            &PluginManifest::new(&["coreelements", "app"], &[]),
+        )
+        .unwrap();
+
+        // The environment is set up before the logs are redirected and GStreamer is
+        // initialised, and plugins are only loaded afterwards.
+        let mut expected = vec![String::from("cache_dir"), String::from("files_dir")];
+        for (name, value) in environment(Path::new("/data/cache"), Path::new("/data/files")) {
+            expected.push(format!("set {} {}", name, value.display()));
+        }
+        expected.extend(
+            [
+                "redirect logs",
+                "gst init",
+                "open libgstcoreelements.so",
+                "call libgstcoreelements.so gst_plugin_coreelements_register",
+                "open libgstapp.so",
+                "call libgstapp.so gst_plugin_app_register",
+            ]
+            .iter()
+            .map(|call| String::from(*call)),
+        );
+        assert_eq!(calls(&journal), expected);
+        assert_eq!(plugins, ["libgstcoreelements.so", "libgstapp.so"]);
+        assert!(report.check().is_ok());
+    }
+
+    #[test]
+    fn init_stops_when_gstreamer_fails() {
+        struct FailingProcess;
+
+        impl Process for FailingProcess {
+            fn set_var(&self, _name: &str, _value: &Path) {}
+
+            fn redirect_logs(&self, _log: Arc<dyn LogSink>) {}
+
+            fn init_gstreamer(&self) -> Result<(), Error> {
+                anyhow::bail!("GStreamer initialization failed")
+            }
+        }
+
+        let journal = Arc::new(Journal::default());
+        let dirs = MockDirectories(PathBuf::from("/data"), journal.clone());
+        let loader = MockLoader::new(journal.clone());
+        let result = init(
+            &dirs,
+            &loader,
+            &FailingProcess,
+            journal.clone(),
+            &PluginManifest::new(&["coreelements"], &[]),
+        );
+        assert!(result.is_err());
+        assert_eq!(calls(&journal), ["cache_dir", "files_dir"]);
+    }
+}
@@ -0,0 +1,371 @@
+//! List of the GStreamer plugins to register at initialisation.
+//!
+//! A manifest has one plugin per line, the name the plugin is registered with, e.g.
+//! `audioconvert` for `libgstaudioconvert.so`. A plugin followed by `optional` may be
+//! missing from the app, the others are required. `#` starts a comment.
+//!
+//! ```text
+//! # Needed by the analysis pipeline
+//! coreelements
+//! app
+//! # Hardware codecs, not on every device
+//! androidmedia optional
+//! ```
+
+use std::fmt;
+use std::str::FromStr;
+
+use derive_more::{Display, Error};
+
+use crate::metrics::push_json_string;
+
+/// Plugins registered when the app provides no manifest.
+pub const DEFAULT_MANIFEST: &str = "\
+coreelements
+coretracers optional
+adder optional
+app
+audioconvert
+audiomixer optional
+audiorate optional
+audioresample
+audiotestsrc
+compositor optional
+gio optional
+overlaycomposition optional
+pango optional
+rawparse optional
+typefindfunctions optional
+videoconvert optional
+videorate optional
+videoscale optional
+videotestsrc optional
+volume optional
+autodetect optional
+videofilter optional
+androidmedia optional
+";
+
+#[derive(Debug, Display, Error)]
+#[display(fmt = "Invalid plugin manifest at line {}: {}", line, reason)]
+pub struct InvalidManifest {
+    pub line: usize,
+    #[error(not(source))]
+    pub reason: String,
+}
+
+#[derive(Debug, Display, Error)]
+#[display(fmt = "Required plugins could not be registered: {}", _0)]
+pub struct MissingPlugins(#[error(not(source))] pub String);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Plugin {
+    pub name: String,
+    /// Initialisation fails when a required plugin cannot be registered.
+    pub required: bool,
+}
+
+/// Plugins to register, in order.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PluginManifest {
+    pub plugins: Vec<Plugin>,
+}
+
+impl Default for PluginManifest {
+    fn default() -> Self {
+        DEFAULT_MANIFEST
+            .parse()
+            .expect("invalid default plugin manifest")
+    }
+}
+
+impl FromStr for PluginManifest {
+    type Err = InvalidManifest;
+
+    fn from_str(manifest: &str) -> Result<Self, InvalidManifest> {
+        let mut plugins: Vec<Plugin> = Vec::new();
+        for (index, line) in manifest.lines().enumerate() {
+            let error = |reason: String| InvalidManifest {
+                line: index + 1,
+                reason,
+            };
+            let line = line.split('#').next().unwrap_or("");
+            let mut words = line.split_whitespace();
+            let name = match words.next() {
+                Some(name) => name,
+                None => continue,
+            };
+            // Names end up in file and symbol names.
+            if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                return Err(error(format!("invalid plugin name {:?}", name)));
+            }
+            let required = match words.next() {
+                None => true,
+                Some("optional") => false,
+                Some(word) => return Err(error(format!("unexpected {:?}", word))),
+            };
+            if let Some(word) = words.next() {
+                return Err(error(format!("unexpected {:?}", word)));
+            }
+            if plugins.iter().any(|plugin| plugin.name == name) {
+                return Err(error(format!("{} listed twice", name)));
+            }
+            plugins.push(Plugin {
+                name: String::from(name),
+                required,
+            });
+        }
+        Ok(PluginManifest { plugins })
+    }
+}
+
+impl PluginManifest {
+    /// Manifest of the `required` plugins followed by the `optional` ones.
+    pub fn new<S: AsRef<str>>(required: &[S], optional: &[S]) -> Self {
+        let plugin = |name: &S, required| Plugin {
+            name: String::from(name.as_ref()),
+            required,
+        };
+        let plugins = required
+            .iter()
+            .map(|name| plugin(name, true))
+            .chain(optional.iter().map(|name| plugin(name, false)))
+            .collect();
+        PluginManifest { plugins }
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.plugins.iter().map(|plugin| plugin.name.as_str())
+    }
+}
+
+/// Outcome of registering a plugin.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PluginStatus {
+    Loaded,
+    /// The library could not be opened, with the reason.
+    MissingLibrary(String),
+    /// The library has no register function, with the reason.
+    MissingSymbol(String),
+    /// The register function was called but GStreamer does not know the plugin.
+    RegistrationFailed,
+}
+
+impl PluginStatus {
+    /// Short name of the status, e.g. `missing_library`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            PluginStatus::Loaded => "loaded",
+            PluginStatus::MissingLibrary(_) => "missing_library",
+            PluginStatus::MissingSymbol(_) => "missing_symbol",
+            PluginStatus::RegistrationFailed => "registration_failed",
+        }
+    }
+
+    pub fn error(&self) -> Option<&str> {
+        match self {
+            PluginStatus::MissingLibrary(error) | PluginStatus::MissingSymbol(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for PluginStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())?;
+        if let Some(error) = self.error() {
+            write!(f, " ({})", error)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PluginReport {
+    pub plugin: Plugin,
+    pub status: PluginStatus,
+}
+
+/// Outcome of registering every plugin of a manifest, in the manifest's order.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct InitReport {
+    pub plugins: Vec<PluginReport>,
+}
+
+impl InitReport {
+    /// Required plugins which were not registered.
+    pub fn missing_required(&self) -> impl Iterator<Item = &PluginReport> {
+        self.plugins
+            .iter()
+            .filter(|report| report.plugin.required && report.status != PluginStatus::Loaded)
+    }
+
+    /// Fail if a required plugin was not registered.
+    pub fn check(&self) -> Result<(), MissingPlugins> {
+        let missing = self
+            .missing_required()
+            .map(|report| format!("{}: {}", report.plugin.name, report.status))
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(MissingPlugins(missing.join(", ")))
+        }
+    }
+
+    /// Serialise as one JSON object per plugin and line, e.g.
+    /// `{"name":"app","required":true,"status":"missing_library","error":"..."}`.
+    pub fn to_json_lines(&self) -> String {
+        let mut json = String::new();
+        for report in &self.plugins {
+            json.push_str("{\"name\":");
+            push_json_string(&mut json, &report.plugin.name);
+            json.push_str(",\"required\":");
+            json.push_str(if report.plugin.required {
+                "true"
+            } else {
+                "false"
+            });
+            json.push_str(",\"status\":");
+            push_json_string(&mut json, report.status.name());
+            json.push_str(",\"error\":");
+            match report.status.error() {
+                Some(error) => push_json_string(&mut json, error),
+                None => json.push_str("null"),
+            }
+            json.push_str("}\n");
+        }
+        json
+    }
+}
+
+impl fmt::Display for InitReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for report in &self.plugins {
+            let required = if report.plugin.required {
+                ""
+            } else {
+                " (optional)"
+            };
+            writeln!(f, "{}{}: {}", report.plugin.name, required, report.status)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let manifest: PluginManifest = "\
+            # comment\n\
+            coreelements\n\
+            \n\
+            androidmedia optional # hardware codecs\n\
+            app\n"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            manifest.plugins,
+            [
+                Plugin {
+                    name: String::from("coreelements"),
+                    required: true
+                },
+                Plugin {
+                    name: String::from("androidmedia"),
+                    required: false
+                },
+                Plugin {
+                    name: String::from("app"),
+                    required: true
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        for (manifest, line) in &[
+            ("app\nlib/gst\n", 2),
+            ("app required\n", 1),
+            ("app optional extra\n", 1),
+            ("app\n\napp optional\n", 3),
+        ] {
+            let err = manifest.parse::<PluginManifest>().unwrap_err();
+            assert_eq!(err.line, *line, "{}", err);
+        }
+    }
+
+    #[test]
+    fn default_manifest() {
+        let manifest = PluginManifest::default();
+        assert_eq!(manifest.names().next(), Some("coreelements"));
+        assert!(manifest
+            .plugins
+            .iter()
+            .any(|plugin| plugin.name == "androidmedia" && !plugin.required));
+        assert_eq!(
+            PluginManifest::new(&["app"], &["volume"]).plugins,
+            [
+                Plugin {
+                    name: String::from("app"),
+                    required: true
+                },
+                Plugin {
+                    name: String::from("volume"),
+                    required: false
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn report() {
+        let report = |name: &str, required, status| PluginReport {
+            plugin: Plugin {
+                name: String::from(name),
+                required,
+            },
+            status,
+        };
+        let mut init = InitReport {
+            plugins: vec![
+                report("coreelements", true, PluginStatus::Loaded),
+                report(
+                    "androidmedia",
+                    false,
+                    PluginStatus::MissingLibrary(String::from("not found")),
+                ),
+            ],
+        };
+        assert!(init.check().is_ok());
+
+        init.plugins.push(report(
+            "app",
+            true,
+            PluginStatus::MissingSymbol(String::from("undefined \"symbol\"")),
+        ));
+        init.plugins.push(report(
+            "audioconvert",
+            true,
+            PluginStatus::RegistrationFailed,
+        ));
+        assert_eq!(
+            init.check().unwrap_err().to_string(),
+            "Required plugins could not be registered: \
+             app: missing_symbol (undefined \"symbol\"), audioconvert: registration_failed"
+        );
+        let lines = init.to_json_lines();
+        assert_eq!(
+            lines.lines().nth(2),
+            Some(
+                "{\"name\":\"app\",\"required\":true,\"status\":\"missing_symbol\",\
+                 \"error\":\"undefined \\\"symbol\\\"\"}"
+            )
+        );
+        assert_eq!(lines.lines().count(), 4);
+    }
+}
@@ -0,0 +1,735 @@
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use gst::gst_element_error;
+use gst::prelude::*;
+use gst_audio::AudioChannelPosition;
+
+use anyhow::{anyhow, Error};
+
+use crate::{MissingElement, CAT};
+
+/// Container the recording is written in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecordFormat {
+    /// The samples as negotiated with the analysis appsink, in a RIFF WAVE file.
+    Wav,
+    /// The samples encoded with `flacenc`.
+    Flac,
+}
+
+impl RecordFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            RecordFormat::Wav => "wav",
+            RecordFormat::Flac => "flac",
+        }
+    }
+}
+
+/// Where and how to record the analysed stream. A new file is started whenever one
+/// of the limits would be exceeded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecordConfig {
+    /// Directory the files are written to, which must exist.
+    pub directory: PathBuf,
+    pub format: RecordFormat,
+    /// Longest duration of one file.
+    pub max_duration: Option<Duration>,
+    /// Largest size of one file in bytes.
+    pub max_size: Option<u64>,
+}
+
+impl RecordConfig {
+    pub fn new<P: Into<PathBuf>>(directory: P, format: RecordFormat) -> Self {
+        RecordConfig {
+            directory: directory.into(),
+            format,
+            max_duration: None,
+            max_size: None,
+        }
+    }
+}
+
+fn make_element(factory: &str) -> Result<gst::Element, MissingElement> {
+    gst::ElementFactory::make(factory, None).map_err(|_| MissingElement(String::from(factory)))
+}
+
+// Link `src` to the analysis `sink` through a tee whose other branch records to files
+// as described by `config`. `src` and `sink` are already in `pipeline`.
+pub(crate) fn link_recording(
+    pipeline: &gst::Pipeline,
+    src: &gst::Element,
+    sink: &gst::Element,
+    config: &RecordConfig,
+) -> Result<(), Error> {
+    let tee = make_element("tee")?;
+    // Each branch of the tee needs its own queue, otherwise the first sink to preroll
+    // blocks the tee and the other one never gets data.
+    let analysis_queue = make_element("queue")?;
+    let record_queue = make_element("queue")?;
+    let record_sink = make_element("appsink")?;
+    let encoder = match config.format {
+        RecordFormat::Wav => Vec::new(),
+        RecordFormat::Flac => vec![make_element("audioconvert")?, make_element("flacenc")?],
+    };
+
+    pipeline.add_many(&[&tee, &analysis_queue, &record_queue, &record_sink])?;
+    for element in &encoder {
+        pipeline.add(element)?;
+    }
+    gst::Element::link_many(&[src, &tee, &analysis_queue, sink])?;
+    let mut branch = vec![&tee, &record_queue];
+    branch.extend(encoder.iter());
+    branch.push(&record_sink);
+    gst::Element::link_many(&branch)?;
+
+    // Writing files should not be paced by the clock.
+    record_sink.set_property("sync", &false)?;
+
+    let appsink = record_sink
+        .dynamic_cast::<gst_app::AppSink>()
+        .expect("Sink element is expected to be an appsink!");
+    let recorder = Arc::new(Mutex::new(Recorder::new(config.clone())));
+    let eos_recorder = recorder.clone();
+    appsink.set_callbacks(
+        gst_app::AppSinkCallbacks::builder()
+            .new_sample(move |appsink| {
+                let sample = appsink.pull_sample().map_err(|_| gst::FlowError::Eos)?;
+                recorder.lock().unwrap().write(&sample).map_err(|err| {
+                    gst_element_error!(
+                        appsink,
+                        gst::ResourceError::Write,
+                        ("Failed to record sample"),
+                        ["{}", err]
+                    );
+
+                    gst::FlowError::Error
+                })?;
+
+                Ok(gst::FlowSuccess::Ok)
+            })
+            .eos(move |appsink| {
+                if let Err(err) = eos_recorder.lock().unwrap().finish() {
+                    gst_element_error!(
+                        appsink,
+                        gst::ResourceError::Write,
+                        ("Failed to finish recording"),
+                        ["{}", err]
+                    );
+                }
+            })
+            .build(),
+    );
+
+    Ok(())
+}
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xfffe;
+// What follows the format tag in the GUID of a WAVE_FORMAT_EXTENSIBLE subformat.
+const SUBFORMAT_GUID_SUFFIX: [u8; 14] = [
+    0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71,
+];
+
+// Speaker of `position` in the channel mask of WAVE_FORMAT_EXTENSIBLE.
+fn speaker_bit(position: AudioChannelPosition) -> Option<u32> {
+    let bit = match position {
+        AudioChannelPosition::FrontLeft => 0,
+        AudioChannelPosition::FrontRight => 1,
+        AudioChannelPosition::FrontCenter | AudioChannelPosition::Mono => 2,
+        AudioChannelPosition::Lfe1 => 3,
+        AudioChannelPosition::RearLeft => 4,
+        AudioChannelPosition::RearRight => 5,
+        AudioChannelPosition::FrontLeftOfCenter => 6,
+        AudioChannelPosition::FrontRightOfCenter => 7,
+        AudioChannelPosition::RearCenter => 8,
+        AudioChannelPosition::SideLeft => 9,
+        AudioChannelPosition::SideRight => 10,
+        AudioChannelPosition::TopCenter => 11,
+        AudioChannelPosition::TopFrontLeft => 12,
+        AudioChannelPosition::TopFrontCenter => 13,
+        AudioChannelPosition::TopFrontRight => 14,
+        AudioChannelPosition::TopRearLeft => 15,
+        AudioChannelPosition::TopRearCenter => 16,
+        AudioChannelPosition::TopRearRight => 17,
+        _ => return None,
+    };
+    Some(1 << bit)
+}
+
+// Channel mask of `positions`, 0 for no particular speakers when one of them has no
+// speaker bit or they are not in the order of their bits, which WAV requires.
+fn channel_mask(positions: &[AudioChannelPosition]) -> u32 {
+    let mut mask = 0;
+    for position in positions {
+        match speaker_bit(*position) {
+            Some(bit) if bit > mask => mask |= bit,
+            _ => return 0,
+        }
+    }
+    mask
+}
+
+// Layout of the samples written to a WAV file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct WavFormat {
+    float: bool,
+    bits: u16,
+    channels: u16,
+    rate: u32,
+    // Speakers of the channels, only written in a WAVE_FORMAT_EXTENSIBLE header.
+    channel_mask: u32,
+}
+
+impl WavFormat {
+    fn from_info(info: &gst_audio::AudioInfo) -> Result<Self, Error> {
+        let float = match info.format() {
+            gst_audio::AUDIO_FORMAT_F32 => true,
+            gst_audio::AUDIO_FORMAT_S32
+            | gst_audio::AUDIO_FORMAT_S16
+            | gst_audio::AUDIO_FORMAT_U8 => false,
+            format => return Err(anyhow!("Cannot record {} as WAV", format.to_str())),
+        };
+        Ok(WavFormat {
+            float,
+            bits: info.width() as u16,
+            channels: info.channels() as u16,
+            rate: info.rate(),
+            channel_mask: info.positions().map(channel_mask).unwrap_or(0),
+        })
+    }
+
+    fn frame_size(&self) -> u64 {
+        u64::from(self.channels) * u64::from(self.bits / 8)
+    }
+
+    // More than 2 channels or 16 bits need a WAVE_FORMAT_EXTENSIBLE header.
+    fn extensible(&self) -> bool {
+        self.channels > 2 || self.bits > 16
+    }
+
+    fn header_size(&self) -> u64 {
+        if self.extensible() {
+            68
+        } else {
+            44
+        }
+    }
+
+    // Header for `data_size` bytes of samples, the canonical 44 bytes unless the
+    // format needs WAVE_FORMAT_EXTENSIBLE.
+    fn header(&self, data_size: u64) -> Vec<u8> {
+        let frame_size = self.frame_size() as u16;
+        let format_tag = if self.float {
+            WAVE_FORMAT_IEEE_FLOAT
+        } else {
+            WAVE_FORMAT_PCM
+        };
+        // Sizes above 4 GiB cannot be represented, leave them at the maximum.
+        let riff_size = (self.header_size() - 8 + data_size).min(u64::from(u32::MAX));
+        let data_size = riff_size - (self.header_size() - 8);
+
+        let mut header = Vec::with_capacity(self.header_size() as usize);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&(riff_size as u32).to_le_bytes());
+        header.extend_from_slice(b"WAVEfmt ");
+        if self.extensible() {
+            header.extend_from_slice(&40u32.to_le_bytes());
+            header.extend_from_slice(&WAVE_FORMAT_EXTENSIBLE.to_le_bytes());
+        } else {
+            header.extend_from_slice(&16u32.to_le_bytes());
+            header.extend_from_slice(&format_tag.to_le_bytes());
+        }
+        header.extend_from_slice(&self.channels.to_le_bytes());
+        header.extend_from_slice(&self.rate.to_le_bytes());
+        header.extend_from_slice(&(self.rate * u32::from(frame_size)).to_le_bytes());
+        header.extend_from_slice(&frame_size.to_le_bytes());
+        header.extend_from_slice(&self.bits.to_le_bytes());
+        if self.extensible() {
+            // Size of the extension, valid bits per sample, channel mask and subformat.
+            header.extend_from_slice(&22u16.to_le_bytes());
+            header.extend_from_slice(&self.bits.to_le_bytes());
+            header.extend_from_slice(&self.channel_mask.to_le_bytes());
+            header.extend_from_slice(&format_tag.to_le_bytes());
+            header.extend_from_slice(&SUBFORMAT_GUID_SUFFIX);
+        }
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&(data_size as u32).to_le_bytes());
+        header
+    }
+}
+
+// Audio written to a WAV file between two updates of the sizes in its header, in
+// nanoseconds.
+const WAV_HEADER_INTERVAL: u64 = 1_000_000_000;
+
+// What is written at the start of every file.
+#[derive(Clone, Debug, PartialEq)]
+enum Header {
+    Wav(WavFormat),
+    // The `streamheader` buffers of the encoder caps.
+    Flac(Vec<Vec<u8>>),
+}
+
+impl Header {
+    fn from_caps(format: RecordFormat, caps: &gst::CapsRef) -> Result<Self, Error> {
+        match format {
+            RecordFormat::Wav => {
+                let info = gst_audio::AudioInfo::from_caps(caps)
+                    .map_err(|_| anyhow!("Invalid audio caps {}", caps))?;
+                Ok(Header::Wav(WavFormat::from_info(&info)?))
+            }
+            RecordFormat::Flac => {
+                let structure = caps
+                    .get_structure(0)
+                    .ok_or_else(|| anyhow!("Empty FLAC caps"))?;
+                let streamheader = structure
+                    .get_some::<gst::Array>("streamheader")
+                    .map_err(|_| anyhow!("No streamheader in FLAC caps {}", caps))?;
+                let mut buffers = Vec::new();
+                for value in streamheader.as_slice() {
+                    let buffer = value
+                        .get::<gst::Buffer>()?
+                        .ok_or_else(|| anyhow!("Empty FLAC streamheader buffer"))?;
+                    let map = buffer
+                        .map_readable()
+                        .map_err(|_| anyhow!("Failed to map FLAC streamheader buffer"))?;
+                    buffers.push(map.as_slice().to_vec());
+                }
+                Ok(Header::Flac(buffers))
+            }
+        }
+    }
+
+    fn size(&self) -> u64 {
+        match self {
+            Header::Wav(format) => format.header_size(),
+            Header::Flac(buffers) => buffers.iter().map(|b| b.len() as u64).sum(),
+        }
+    }
+}
+
+// File being written, with the bytes written after the header and their duration in
+// nanoseconds.
+struct Output {
+    file: File,
+    path: PathBuf,
+    size: u64,
+    duration: u64,
+    // Format of a WAV file, whose header holds sizes.
+    wav: Option<WavFormat>,
+    // Duration when the WAV header was last updated.
+    header_updated: u64,
+}
+
+impl Output {
+    // Write the sizes reached so far in the header of a WAV file.
+    fn update_header(&mut self) -> Result<(), Error> {
+        if let Some(format) = self.wav {
+            self.file.seek(SeekFrom::Start(0))?;
+            self.file.write_all(&format.header(self.size))?;
+            self.file.seek(SeekFrom::End(0))?;
+            self.header_updated = self.duration;
+        }
+        Ok(())
+    }
+}
+
+// Writes the samples of the recording branch to files, starting a new file when a
+// limit is reached or the format changes.
+//
+// WAV headers are updated when a file is finished and every `WAV_HEADER_INTERVAL` in
+// between, so that the files stay mostly readable if the process dies. Files following
+// the first one of a FLAC recording start with the stream headers but their frames keep
+// counting from the start of the recording.
+struct Recorder {
+    config: RecordConfig,
+    // Seconds since the epoch when recording started, shared by all the file names.
+    started: u64,
+    index: u32,
+    caps: Option<gst::Caps>,
+    header: Option<Header>,
+    output: Option<Output>,
+}
+
+impl Recorder {
+    fn new(config: RecordConfig) -> Self {
+        Recorder {
+            config,
+            started: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            index: 0,
+            caps: None,
+            header: None,
+            output: None,
+        }
+    }
+
+    fn write(&mut self, sample: &gst::Sample) -> Result<(), Error> {
+        let caps = sample
+            .get_caps()
+            .ok_or_else(|| anyhow!("Sample without caps"))?;
+        if self.caps.as_ref().map(|c| c.as_ref()) != Some(caps) {
+            let header = Header::from_caps(self.config.format, caps)?;
+            if self.header.as_ref() != Some(&header) {
+                self.finish()?;
+                self.header = Some(header);
+            }
+            self.caps = Some(caps.to_owned());
+        }
+
+        let buffer = sample
+            .get_buffer()
+            .ok_or_else(|| anyhow!("Sample without buffer"))?;
+        // The encoder sends its headers as buffers too, they are already written from
+        // the caps at the start of every file.
+        if buffer.get_flags().contains(gst::BufferFlags::HEADER) {
+            return Ok(());
+        }
+        let map = buffer
+            .map_readable()
+            .map_err(|_| anyhow!("Failed to map buffer readable"))?;
+
+        match self.header.clone().expect("Header was just set") {
+            Header::Wav(format) => self.write_wav(format, map.as_slice()),
+            header => {
+                let duration = buffer.get_duration().nseconds().unwrap_or(0);
+                self.write_encoded(&header, map.as_slice(), duration)
+            }
+        }
+    }
+
+    // Write PCM `data`, splitting it on a frame boundary when a limit falls inside.
+    fn write_wav(&mut self, format: WavFormat, mut data: &[u8]) -> Result<(), Error> {
+        let header = Header::Wav(format);
+        let frame_size = format.frame_size();
+        while !data.is_empty() {
+            let written = match self.output {
+                Some(ref output) => output.size / frame_size,
+                None => {
+                    self.open(&header)?;
+                    continue;
+                }
+            };
+
+            // Frames which still fit in the current file, at least one in a new file.
+            let mut room = data.len() as u64 / frame_size;
+            if let Some(max_size) = self.config.max_size {
+                let frames = max_size.saturating_sub(format.header_size()) / frame_size;
+                room = room.min(frames.saturating_sub(written));
+            }
+            if let Some(max_duration) = self.config.max_duration {
+                let frames = (max_duration.as_secs_f64() * f64::from(format.rate)).round() as u64;
+                room = room.min(frames.saturating_sub(written));
+            }
+            if written == 0 {
+                room = room.max(1);
+            } else if room == 0 {
+                self.open(&header)?;
+                continue;
+            }
+
+            let (chunk, rest) = data.split_at((room * frame_size) as usize);
+            let duration = room * 1_000_000_000 / u64::from(format.rate);
+            self.append(chunk, duration)?;
+            if let Some(ref mut output) = self.output {
+                if output.duration - output.header_updated >= WAV_HEADER_INTERVAL {
+                    output.update_header()?;
+                }
+            }
+            data = rest;
+        }
+        Ok(())
+    }
+
+    // Write an encoded buffer lasting `duration` nanoseconds, starting a new file first
+    // if it would not fit in the current one.
+    fn write_encoded(&mut self, header: &Header, data: &[u8], duration: u64) -> Result<(), Error> {
+        let full = self.output.as_ref().is_some_and(|output| {
+            let size = header.size() + output.size + data.len() as u64;
+            let length = Duration::from_nanos(output.duration + duration);
+            output.size > 0
+                && (self.config.max_size.is_some_and(|max| size > max)
+                    || self.config.max_duration.is_some_and(|max| length > max))
+        });
+        if full || self.output.is_none() {
+            self.open(header)?;
+        }
+        self.append(data, duration)
+    }
+
+    fn append(&mut self, data: &[u8], duration: u64) -> Result<(), Error> {
+        let output = self
+            .output
+            .as_mut()
+            .expect("Output is opened before writing");
+        output.file.write_all(data)?;
+        output.size += data.len() as u64;
+        output.duration += duration;
+        Ok(())
+    }
+
+    // Complete the current file, if any.
+    fn finish(&mut self) -> Result<(), Error> {
+        if let Some(mut output) = self.output.take() {
+            output.update_header()?;
+            gst_debug!(CAT, "finished {}", output.path.display());
+        }
+        Ok(())
+    }
+
+    fn open(&mut self, header: &Header) -> Result<(), Error> {
+        self.finish()?;
+        // A recording started in the same second may already use the name: skip to the
+        // next index rather than overwrite its file.
+        let (mut file, path) = loop {
+            let path = self.config.directory.join(format!(
+                "androidsink-{}-{:04}.{}",
+                self.started,
+                self.index,
+                self.config.format.extension()
+            ));
+            self.index += 1;
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => break (file, path),
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(anyhow!("Failed to create {}: {}", path.display(), err)),
+            }
+        };
+
+        gst_info!(CAT, "recording to {}", path.display());
+        let wav = match header {
+            Header::Wav(format) => {
+                file.write_all(&format.header(0))?;
+                Some(*format)
+            }
+            Header::Flac(buffers) => {
+                for buffer in buffers {
+                    file.write_all(buffer)?;
+                }
+                None
+            }
+        };
+
+        self.output = Some(Output {
+            file,
+            path,
+            size: 0,
+            duration: 0,
+            wav,
+            header_updated: 0,
+        });
+        Ok(())
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        if let Err(err) = self.finish() {
+            gst_warning!(CAT, "failed to finish recording: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+    }
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        let mut value = [0; 4];
+        value.copy_from_slice(&bytes[offset..offset + 4]);
+        u32::from_le_bytes(value)
+    }
+
+    // Empty directory for the files of one test, removed by the test once done.
+    fn directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!(
+            "androidsink-record-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    // Frames of every file written in `directory`, in order, checking the header sizes
+    // against the length of the file.
+    fn recorded_frames(directory: &Path, format: WavFormat) -> Vec<u64> {
+        let mut paths = std::fs::read_dir(directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<_>>();
+        paths.sort();
+        paths
+            .iter()
+            .map(|path| {
+                let bytes = std::fs::read(path).unwrap();
+                let data_size = bytes.len() as u64 - format.header_size();
+                assert_eq!(
+                    bytes[..format.header_size() as usize],
+                    format.header(data_size)[..]
+                );
+                assert_eq!(u64::from(u32_at(&bytes, 4)), bytes.len() as u64 - 8);
+                data_size / format.frame_size()
+            })
+            .collect()
+    }
+
+    // Write `frames` frames of `format` in buffers of 64 frames.
+    fn record(config: RecordConfig, format: WavFormat, frames: u64) {
+        let mut recorder = Recorder::new(config);
+        let data = vec![0x55; (frames * format.frame_size()) as usize];
+        for buffer in data.chunks(64 * format.frame_size() as usize) {
+            recorder.write_wav(format, buffer).unwrap();
+        }
+    }
+
+    #[test]
+    fn canonical_header() {
+        let format = WavFormat {
+            float: false,
+            bits: 16,
+            channels: 2,
+            rate: 48_000,
+            channel_mask: 0x3,
+        };
+        let header = format.header(1000);
+        assert_eq!(header.len(), 44);
+        assert_eq!(&header[..4], b"RIFF");
+        assert_eq!(u32_at(&header, 4), 1036);
+        assert_eq!(&header[8..16], b"WAVEfmt ");
+        assert_eq!(u32_at(&header, 16), 16);
+        assert_eq!(u16_at(&header, 20), WAVE_FORMAT_PCM);
+        assert_eq!(u16_at(&header, 22), 2);
+        assert_eq!(u32_at(&header, 24), 48_000);
+        assert_eq!(u32_at(&header, 28), 192_000);
+        assert_eq!(u16_at(&header, 32), 4);
+        assert_eq!(u16_at(&header, 34), 16);
+        assert_eq!(&header[36..40], b"data");
+        assert_eq!(u32_at(&header, 40), 1000);
+    }
+
+    #[test]
+    fn extensible_header() {
+        let positions = [
+            AudioChannelPosition::FrontLeft,
+            AudioChannelPosition::FrontRight,
+            AudioChannelPosition::FrontCenter,
+            AudioChannelPosition::Lfe1,
+            AudioChannelPosition::RearLeft,
+            AudioChannelPosition::RearRight,
+        ];
+        let format = WavFormat {
+            float: true,
+            bits: 32,
+            channels: 6,
+            rate: 48_000,
+            channel_mask: channel_mask(&positions),
+        };
+        let header = format.header(2400);
+        assert_eq!(header.len(), 68);
+        assert_eq!(u32_at(&header, 4), 2460);
+        assert_eq!(u32_at(&header, 16), 40);
+        assert_eq!(u16_at(&header, 20), WAVE_FORMAT_EXTENSIBLE);
+        assert_eq!(u16_at(&header, 22), 6);
+        assert_eq!(u16_at(&header, 32), 24);
+        assert_eq!(u16_at(&header, 34), 32);
+        assert_eq!(u16_at(&header, 36), 22);
+        assert_eq!(u16_at(&header, 38), 32);
+        assert_eq!(u32_at(&header, 40), 0x3f);
+        assert_eq!(u16_at(&header, 44), WAVE_FORMAT_IEEE_FLOAT);
+        assert_eq!(header[46..60], SUBFORMAT_GUID_SUFFIX);
+        assert_eq!(&header[60..64], b"data");
+        assert_eq!(u32_at(&header, 64), 2400);
+
+        // Channels out of the WAV order have no speakers.
+        assert_eq!(
+            channel_mask(&[
+                AudioChannelPosition::FrontRight,
+                AudioChannelPosition::FrontLeft
+            ]),
+            0
+        );
+    }
+
+    #[test]
+    fn rotation_by_size() {
+        gst::init().unwrap();
+
+        let directory = directory("size");
+        let format = WavFormat {
+            float: false,
+            bits: 16,
+            channels: 2,
+            rate: 48_000,
+            channel_mask: 0,
+        };
+        let mut config = RecordConfig::new(&directory, RecordFormat::Wav);
+        // 100 frames after the header.
+        config.max_size = Some(44 + 400);
+        record(config, format, 250);
+
+        assert_eq!(recorded_frames(&directory, format), [100, 100, 50]);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn rotation_by_duration() {
+        gst::init().unwrap();
+
+        let directory = directory("duration");
+        let format = WavFormat {
+            float: true,
+            bits: 32,
+            channels: 1,
+            rate: 8000,
+            channel_mask: 0x4,
+        };
+        let mut config = RecordConfig::new(&directory, RecordFormat::Wav);
+        // 80 frames.
+        config.max_duration = Some(Duration::from_millis(10));
+        record(config, format, 200);
+
+        assert_eq!(recorded_frames(&directory, format), [80, 80, 40]);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn existing_files_kept() {
+        gst::init().unwrap();
+
+        let directory = directory("existing");
+        let format = WavFormat {
+            float: false,
+            bits: 16,
+            channels: 1,
+            rate: 8000,
+            channel_mask: 0,
+        };
+        let mut recorder = Recorder::new(RecordConfig::new(&directory, RecordFormat::Wav));
+        // The first file of another recording started in the same second.
+        let name = |index| format!("androidsink-{}-{:04}.wav", recorder.started, index);
+        let existing = directory.join(name(0));
+        std::fs::write(&existing, b"taken").unwrap();
+        let recorded = directory.join(name(1));
+
+        recorder.write_wav(format, &[0x55; 64]).unwrap();
+        recorder.finish().unwrap();
+        assert_eq!(std::fs::read(&existing).unwrap(), b"taken");
+        assert_eq!(std::fs::metadata(&recorded).unwrap().len(), 44 + 64);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
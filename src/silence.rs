@@ -0,0 +1,363 @@
+use std::time::Duration;
+
+use anyhow::Error;
+
+use crate::consumer::FrameAccumulator;
+use crate::level::to_db;
+use crate::{SampleConsumer, Samples};
+
+/// Name of the element message posted when the signal has been silent for the
+/// configured duration. Its `time` field is the start of the silence.
+pub const SILENCE_START_MESSAGE: &str = "androidsink-silence-start";
+/// Name of the element message posted when the signal comes back after a silence,
+/// with the `time` the silence started and its `duration`.
+pub const SILENCE_END_MESSAGE: &str = "androidsink-silence-end";
+/// Name of the element message posted after a run of exact zeros, with the `time` it
+/// started and its `duration`.
+pub const DROPOUT_MESSAGE: &str = "androidsink-dropout";
+
+/// Event raised by the `SilenceDetector`. Times are on the timeline of the buffer
+/// timestamps.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SilenceEvent {
+    /// The level has been below the threshold since `start` for the minimum duration.
+    SilenceStart { start: gst::ClockTime },
+    /// The level went back above the threshold.
+    SilenceEnd {
+        start: gst::ClockTime,
+        duration: gst::ClockTime,
+    },
+    /// Every channel was exactly zero, after some signal had been seen.
+    Dropout {
+        start: gst::ClockTime,
+        duration: gst::ClockTime,
+    },
+}
+
+impl SilenceEvent {
+    /// Structure of the element message reporting the event.
+    pub fn to_structure(&self) -> gst::Structure {
+        match *self {
+            SilenceEvent::SilenceStart { start } => gst::Structure::builder(SILENCE_START_MESSAGE)
+                .field("time", &start)
+                .build(),
+            SilenceEvent::SilenceEnd { start, duration } => {
+                gst::Structure::builder(SILENCE_END_MESSAGE)
+                    .field("time", &start)
+                    .field("duration", &duration)
+                    .build()
+            }
+            SilenceEvent::Dropout { start, duration } => gst::Structure::builder(DROPOUT_MESSAGE)
+                .field("time", &start)
+                .field("duration", &duration)
+                .build(),
+        }
+    }
+}
+
+/// Thresholds of the `SilenceDetector`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SilenceSettings {
+    /// Buffers whose loudest channel has a lower RMS in dBFS are silent.
+    pub threshold_db: f64,
+    /// How long the signal must stay silent before silence is reported.
+    pub min_silence: Duration,
+    /// Shortest run of exact zeros reported as a dropout.
+    pub min_dropout: Duration,
+}
+
+impl Default for SilenceSettings {
+    fn default() -> Self {
+        SilenceSettings {
+            threshold_db: -60.0,
+            min_silence: Duration::from_secs(2),
+            min_dropout: Duration::from_millis(1),
+        }
+    }
+}
+
+/// Detects silences from the per-buffer level and digital dropouts from runs of exact
+/// zeros, and posts them as element messages on the bus.
+#[derive(Debug)]
+pub struct SilenceDetector {
+    settings: SilenceSettings,
+    frames: FrameAccumulator,
+    silence_start: Option<u64>,
+    silence_reported: bool,
+    // Dropouts are only reported once the signal has been non-zero, so that a source
+    // starting with digital silence is not flagged.
+    seen_signal: bool,
+    zeros_start: Option<u64>,
+}
+
+impl Default for SilenceDetector {
+    fn default() -> Self {
+        SilenceDetector::new(SilenceSettings::default())
+    }
+}
+
+impl SilenceDetector {
+    pub fn new(settings: SilenceSettings) -> Self {
+        SilenceDetector {
+            settings,
+            frames: FrameAccumulator::default(),
+            silence_start: None,
+            silence_reported: false,
+            seen_signal: false,
+            zeros_start: None,
+        }
+    }
+
+    /// Whether a silence is currently reported.
+    pub fn is_silent(&self) -> bool {
+        self.silence_reported
+    }
+
+    /// Check a buffer of interleaved `samples` with `channels` channels at `rate` Hz
+    /// starting at timestamp `pts`.
+    pub fn process(
+        &mut self,
+        samples: &[f32],
+        channels: usize,
+        rate: u32,
+        pts: gst::ClockTime,
+    ) -> Vec<SilenceEvent> {
+        self.frames.start_buffer(rate, pts);
+        let frames = samples.len() / channels;
+        if frames == 0 {
+            return Vec::new();
+        }
+        let end = self.frames.time(frames);
+        let mut events = Vec::new();
+
+        let min_dropout = self.settings.min_dropout.as_nanos() as u64;
+        for (frame, values) in samples.chunks_exact(channels).enumerate() {
+            let time = self.frames.time(frame);
+            if values.iter().all(|sample| *sample == 0.0) {
+                if self.seen_signal && self.zeros_start.is_none() {
+                    self.zeros_start = Some(time);
+                }
+            } else {
+                self.seen_signal = true;
+                if let Some(start) = self.zeros_start.take() {
+                    if time.saturating_sub(start) >= min_dropout {
+                        events.push(SilenceEvent::Dropout {
+                            start: gst::ClockTime::from_nseconds(start),
+                            duration: gst::ClockTime::from_nseconds(time.saturating_sub(start)),
+                        });
+                    }
+                }
+            }
+        }
+
+        let level_db = (0..channels)
+            .map(|channel| {
+                let sum: f64 = samples
+                    .iter()
+                    .skip(channel)
+                    .step_by(channels)
+                    .map(|sample| f64::from(*sample) * f64::from(*sample))
+                    .sum();
+                to_db((sum / frames as f64).sqrt())
+            })
+            .fold(f64::NEG_INFINITY, f64::max);
+        if level_db < self.settings.threshold_db {
+            let start = *self.silence_start.get_or_insert(self.frames.position());
+            if !self.silence_reported
+                && end.saturating_sub(start) >= self.settings.min_silence.as_nanos() as u64
+            {
+                self.silence_reported = true;
+                events.push(SilenceEvent::SilenceStart {
+                    start: gst::ClockTime::from_nseconds(start),
+                });
+            }
+        } else if let Some(start) = self.silence_start.take() {
+            if self.silence_reported {
+                self.silence_reported = false;
+                let duration = self.frames.position().saturating_sub(start);
+                events.push(SilenceEvent::SilenceEnd {
+                    start: gst::ClockTime::from_nseconds(start),
+                    duration: gst::ClockTime::from_nseconds(duration),
+                });
+            }
+        }
+
+        self.frames.advance(frames);
+        events
+    }
+
+    /// Events of the silence and the run of zeros still going on at the end of the
+    /// stream, ending them there. A silence shorter than the minimum is not reported.
+    pub fn finish(&mut self) -> Vec<SilenceEvent> {
+        let mut events = Vec::new();
+        if let Some(start) = self.zeros_start.take() {
+            let duration = self.frames.position().saturating_sub(start);
+            if duration >= self.settings.min_dropout.as_nanos() as u64 {
+                events.push(SilenceEvent::Dropout {
+                    start: gst::ClockTime::from_nseconds(start),
+                    duration: gst::ClockTime::from_nseconds(duration),
+                });
+            }
+        }
+        if let Some(start) = self.silence_start.take() {
+            if self.silence_reported {
+                self.silence_reported = false;
+                events.push(SilenceEvent::SilenceEnd {
+                    start: gst::ClockTime::from_nseconds(start),
+                    duration: gst::ClockTime::from_nseconds(
+                        self.frames.position().saturating_sub(start),
+                    ),
+                });
+            }
+        }
+        events
+    }
+
+    // Report `events` as metrics and element messages.
+    fn report(samples: &Samples, events: Vec<SilenceEvent>) -> Result<(), Error> {
+        for event in events {
+            let metric = match event {
+                SilenceEvent::SilenceStart { start } => samples
+                    .metric("silence")
+                    .with_timestamp(start)
+                    .with_value("silent", 1.0),
+                SilenceEvent::SilenceEnd { start, duration } => samples
+                    .metric("silence")
+                    .with_timestamp(start)
+                    .with_value("silent", 0.0)
+                    .with_value("duration", duration),
+                SilenceEvent::Dropout { start, duration } => samples
+                    .metric("dropout")
+                    .with_timestamp(start)
+                    .with_value("duration", duration),
+            };
+            samples.report(metric);
+            samples.post(event.to_structure())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl SampleConsumer for SilenceDetector {
+    fn consume(&mut self, samples: &Samples) -> Result<(), Error> {
+        let events = self.process(
+            samples.data,
+            samples.channels(),
+            samples.info.rate(),
+            samples.pts,
+        );
+        SilenceDetector::report(samples, events)
+    }
+
+    fn end_of_stream(&mut self, samples: &Samples) -> Result<(), Error> {
+        let events = self.finish();
+        SilenceDetector::report(samples, events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 8000;
+
+    fn ms(value: u64) -> gst::ClockTime {
+        gst::ClockTime::from_mseconds(value)
+    }
+
+    // Feed `detector` with `frames` mono frames of `value` starting at `start` ms, in
+    // buffers of 80 frames (10 ms).
+    fn feed(
+        detector: &mut SilenceDetector,
+        value: f32,
+        start: u64,
+        frames: usize,
+    ) -> Vec<SilenceEvent> {
+        let samples = vec![value; frames];
+        samples
+            .chunks(80)
+            .enumerate()
+            .flat_map(|(index, buffer)| {
+                detector.process(buffer, 1, RATE, ms(start + 10 * index as u64))
+            })
+            .collect()
+    }
+
+    fn detector() -> SilenceDetector {
+        SilenceDetector::new(SilenceSettings {
+            threshold_db: -60.0,
+            min_silence: Duration::from_millis(500),
+            min_dropout: Duration::from_millis(20),
+        })
+    }
+
+    #[test]
+    fn short_gap_ignored() {
+        let mut detector = detector();
+        let mut events = feed(&mut detector, 0.5, 0, 800);
+        events.extend(feed(&mut detector, 0.0001, 100, 2400));
+        events.extend(feed(&mut detector, 0.5, 400, 800));
+        events.extend(detector.finish());
+        assert_eq!(events, []);
+    }
+
+    #[test]
+    fn long_gap_reported() {
+        let mut detector = detector();
+        let mut events = feed(&mut detector, 0.5, 0, 800);
+        events.extend(feed(&mut detector, 0.0001, 100, 8000));
+        assert_eq!(events, [SilenceEvent::SilenceStart { start: ms(100) }]);
+        assert!(detector.is_silent());
+
+        let events = feed(&mut detector, 0.5, 1100, 800);
+        assert_eq!(
+            events,
+            [SilenceEvent::SilenceEnd {
+                start: ms(100),
+                duration: ms(1000),
+            }]
+        );
+        assert!(!detector.is_silent());
+    }
+
+    #[test]
+    fn dropout_detected() {
+        let mut detector = detector();
+        // Zeros before any signal are not a dropout.
+        let mut events = feed(&mut detector, 0.0, 0, 400);
+        events.extend(feed(&mut detector, 0.5, 50, 800));
+        events.extend(feed(&mut detector, 0.0, 150, 240));
+        events.extend(feed(&mut detector, 0.5, 180, 800));
+        assert_eq!(
+            events,
+            [SilenceEvent::Dropout {
+                start: ms(150),
+                duration: ms(30),
+            }]
+        );
+    }
+
+    #[test]
+    fn open_runs_reported_at_end() {
+        let mut detector = detector();
+        let mut events = feed(&mut detector, 0.5, 0, 800);
+        events.extend(feed(&mut detector, 0.0, 100, 8000));
+        events.extend(detector.finish());
+        assert_eq!(
+            events,
+            [
+                SilenceEvent::SilenceStart { start: ms(100) },
+                SilenceEvent::Dropout {
+                    start: ms(100),
+                    duration: ms(1000),
+                },
+                SilenceEvent::SilenceEnd {
+                    start: ms(100),
+                    duration: ms(1000),
+                },
+            ]
+        );
+        assert_eq!(detector.finish(), []);
+    }
+}
@@ -0,0 +1,335 @@
+use std::f64::consts::PI;
+
+use anyhow::Error;
+
+use crate::consumer::FrameAccumulator;
+use crate::fft;
+use crate::level::to_db;
+use crate::{SampleConsumer, Samples};
+
+/// Window applied to every FFT frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Window {
+    Rectangular,
+    Hann,
+    Hamming,
+    Blackman,
+    /// 4-term Blackman-Harris, for the lowest leakage.
+    BlackmanHarris,
+}
+
+impl Window {
+    /// Periodic window of `size` coefficients.
+    pub fn coefficients(self, size: usize) -> Vec<f64> {
+        let cosines: &[f64] = match self {
+            Window::Rectangular => &[1.0],
+            Window::Hann => &[0.5, 0.5],
+            Window::Hamming => &[0.54, 0.46],
+            Window::Blackman => &[0.42, 0.5, 0.08],
+            Window::BlackmanHarris => &[0.358_75, 0.488_29, 0.141_28, 0.011_68],
+        };
+        (0..size)
+            .map(|n| {
+                let x = 2.0 * PI * n as f64 / size as f64;
+                cosines
+                    .iter()
+                    .enumerate()
+                    .map(|(k, a)| {
+                        let a = if k % 2 == 0 { *a } else { -*a };
+                        a * (k as f64 * x).cos()
+                    })
+                    .sum()
+            })
+            .collect()
+    }
+}
+
+// Preferred numbers of the R10 series in hundredths, giving the nominal center
+// frequencies of ISO 266 for every decade.
+const R10: [u32; 10] = [100, 125, 160, 200, 250, 315, 400, 500, 630, 800];
+
+// Nominal frequency of the band `index` tenths of a decade away from 1 kHz.
+fn nominal_frequency(index: i32) -> f64 {
+    let mantissa = f64::from(R10[index.rem_euclid(10) as usize]);
+    let exponent = index.div_euclid(10) + 1;
+    if exponent >= 0 {
+        mantissa * 10f64.powi(exponent)
+    } else {
+        mantissa / 10f64.powi(-exponent)
+    }
+}
+
+/// Fractional octave bands on the base-10 series, centred on 1 kHz.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bands {
+    Octave,
+    ThirdOctave,
+}
+
+impl Bands {
+    // Nominal and exact center, lower and upper edge of every band from 16 Hz
+    // (octave) or 20 Hz (third octave) up to `nyquist`.
+    fn layout(self, nyquist: f64) -> Vec<(f64, f64, f64, f64)> {
+        let (first, step) = match self {
+            Bands::Octave => (-18, 3),
+            Bands::ThirdOctave => (-17, 1),
+        };
+        let half_width = f64::from(step) / 20.0;
+        (first..)
+            .step_by(step as usize)
+            .map(|index| {
+                let center = 1000.0 * 10f64.powf(f64::from(index) / 10.0);
+                (
+                    nominal_frequency(index),
+                    center,
+                    center / 10f64.powf(half_width),
+                    center * 10f64.powf(half_width),
+                )
+            })
+            .take_while(|(_, _, _, upper)| *upper <= nyquist)
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Band {
+    /// Nominal center frequency in Hz from ISO 266, such as 31.5 or 2000, naming the
+    /// band.
+    pub nominal: f64,
+    /// Exact center frequency in Hz on the base-10 series, such as 1995.3.
+    pub center: f64,
+    /// Energy in the band in dBFS, a full-scale sine reads -3 dB like its RMS.
+    pub level_db: f64,
+}
+
+/// Spectrum of one FFT frame.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Spectrum {
+    /// Buffer timestamp at which the frame starts.
+    pub start: gst::ClockTime,
+    /// Frequency step between bins in Hz.
+    pub bin_width: f64,
+    /// Amplitude of bins 0 to size / 2 in dBFS, a full-scale sine centred on a bin
+    /// reads 0 dB.
+    pub magnitudes_db: Vec<f64>,
+    pub bands: Vec<Band>,
+}
+
+impl Spectrum {
+    /// Frequency and amplitude of the strongest bin above DC, NaN bins ignored.
+    pub fn peak(&self) -> Option<(f64, f64)> {
+        self.magnitudes_db
+            .iter()
+            .enumerate()
+            .skip(1)
+            .filter(|(_, db)| !db.is_nan())
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(bin, db)| (bin as f64 * self.bin_width, *db))
+    }
+}
+
+/// Windowed FFT over the channels mixed down to mono. Samples are accumulated
+/// across buffers, a spectrum is produced every `size * (1 - overlap)` samples.
+#[derive(Debug)]
+pub struct SpectrumAnalyser {
+    size: usize,
+    hop: usize,
+    window: Vec<f64>,
+    window_sum: f64,
+    window_power: f64,
+    bands: Bands,
+    frames: FrameAccumulator,
+}
+
+impl Default for SpectrumAnalyser {
+    /// 4096 point Hann window with 50% overlap and third octave bands.
+    fn default() -> Self {
+        SpectrumAnalyser::new(4096, Window::Hann, 0.5, Bands::ThirdOctave)
+    }
+}
+
+impl SpectrumAnalyser {
+    /// `size` must be a power of two and `overlap` the fraction of a frame shared with
+    /// the next one, in `[0, 1)`.
+    pub fn new(size: usize, window: Window, overlap: f64, bands: Bands) -> Self {
+        assert!(size.is_power_of_two(), "FFT size must be a power of two");
+        assert!((0.0..1.0).contains(&overlap), "overlap must be in [0, 1)");
+
+        let window = window.coefficients(size);
+        SpectrumAnalyser {
+            size,
+            hop: ((size as f64 * (1.0 - overlap)).round() as usize).max(1),
+            window_sum: window.iter().sum(),
+            window_power: window.iter().map(|w| w * w).sum(),
+            window,
+            bands,
+            frames: FrameAccumulator::default(),
+        }
+    }
+
+    /// Add interleaved `samples` with `channels` channels at `rate` Hz starting at
+    /// timestamp `pts`, and return the spectra of every frame completed by them.
+    pub fn process(
+        &mut self,
+        samples: &[f32],
+        channels: usize,
+        rate: u32,
+        pts: gst::ClockTime,
+    ) -> Vec<Spectrum> {
+        self.frames.start_buffer(rate, pts);
+        self.frames.push(samples, channels);
+
+        let mut spectra = Vec::new();
+        while let Some(frame) = self.frames.block(self.size) {
+            spectra.push(self.analyse(frame));
+            self.frames.advance(self.hop);
+        }
+        spectra
+    }
+
+    // Spectrum of the frame at the current position.
+    fn analyse(&self, frame: &[f64]) -> Spectrum {
+        let power = fft::power_spectrum(frame, &self.window);
+        let last = power.len() - 1;
+        let rate = f64::from(self.frames.rate());
+        let bin_width = rate / self.size as f64;
+
+        let magnitudes_db = power
+            .iter()
+            .enumerate()
+            .map(|(bin, p)| {
+                let scale = if bin == 0 || bin == last { 1.0 } else { 2.0 };
+                to_db(scale * p.sqrt() / self.window_sum)
+            })
+            .collect();
+
+        // Scale so that summing all bins gives the mean square of the frame.
+        let norm = self.size as f64 * self.window_power;
+        let bands = self
+            .bands
+            .layout(rate / 2.0)
+            .into_iter()
+            .map(|(nominal, center, lower, upper)| {
+                let energy: f64 = power
+                    .iter()
+                    .enumerate()
+                    .filter(|(bin, _)| {
+                        let frequency = *bin as f64 * bin_width;
+                        frequency >= lower && frequency < upper
+                    })
+                    .map(|(bin, p)| if bin == 0 || bin == last { *p } else { 2.0 * p })
+                    .sum();
+                Band {
+                    nominal,
+                    center,
+                    level_db: 10.0 * (energy / norm).log10(),
+                }
+            })
+            .collect();
+
+        Spectrum {
+            start: gst::ClockTime::from_nseconds(self.frames.position()),
+            bin_width,
+            magnitudes_db,
+            bands,
+        }
+    }
+}
+
+impl SampleConsumer for SpectrumAnalyser {
+    fn consume(&mut self, samples: &Samples) -> Result<(), Error> {
+        let spectra = self.process(
+            samples.data,
+            samples.channels(),
+            samples.info.rate(),
+            samples.pts,
+        );
+        for spectrum in spectra {
+            if let Some((frequency, db)) = spectrum.peak() {
+                samples.report(
+                    samples
+                        .metric("spectrum_peak")
+                        .with_timestamp(spectrum.start)
+                        .with_value("frequency", frequency)
+                        .with_value("level_db", db),
+                );
+            }
+            // One value per band, named after its nominal center frequency in Hz.
+            let bands = spectrum.bands.iter().fold(
+                samples
+                    .metric("spectrum_bands")
+                    .with_timestamp(spectrum.start),
+                |metric, band| metric.with_value(&band.nominal.to_string(), band.level_db),
+            );
+            samples.report(bands);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 48_000;
+
+    // Full-scale sine of `frequency` Hz, one frame of the default analyser long.
+    fn sine(frequency: f64) -> Vec<f32> {
+        (0..4096)
+            .map(|n| (2.0 * PI * frequency * n as f64 / f64::from(RATE)).sin() as f32)
+            .collect()
+    }
+
+    #[test]
+    fn window_coefficients() {
+        assert_eq!(Window::Rectangular.coefficients(3), [1.0; 3]);
+        let hann = Window::Hann.coefficients(4);
+        for (coefficient, expected) in hann.iter().zip(&[0.0, 0.5, 1.0, 0.5]) {
+            assert!((coefficient - expected).abs() < 1e-12, "{:?}", hann);
+        }
+        // Periodic: symmetric around the middle, the last point left out.
+        let blackman_harris = Window::BlackmanHarris.coefficients(64);
+        assert!(blackman_harris[0] < 1e-4);
+        assert!((blackman_harris[32] - 1.0).abs() < 1e-12);
+        for n in 1..32 {
+            assert!((blackman_harris[n] - blackman_harris[64 - n]).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn peak_of_tone() {
+        // Centred on bin 100 of 11.72 Hz.
+        let frequency = 100.0 * f64::from(RATE) / 4096.0;
+        let mut analyser = SpectrumAnalyser::default();
+        let start = gst::ClockTime::from_seconds(3);
+        let spectra = analyser.process(&sine(frequency), 1, RATE, start);
+        assert_eq!(spectra.len(), 1);
+        assert_eq!(spectra[0].start, start);
+        let (peak, db) = spectra[0].peak().unwrap();
+        assert_eq!(peak, frequency);
+        assert!(db.abs() < 0.01, "{} dB", db);
+    }
+
+    #[test]
+    fn band_level() {
+        let mut analyser = SpectrumAnalyser::default();
+        let spectrum = analyser
+            .process(&sine(1000.0), 1, RATE, gst::ClockTime::none())
+            .remove(0);
+        let nominal = spectrum
+            .bands
+            .iter()
+            .map(|band| band.nominal)
+            .collect::<Vec<_>>();
+        assert_eq!(nominal[..4], [20.0, 25.0, 31.5, 40.0]);
+        assert_eq!(nominal[nominal.len() - 1], 20_000.0);
+        for label in &[80.0, 125.0, 2000.0, 4000.0, 8000.0] {
+            assert!(nominal.contains(label), "no {} Hz band", label);
+        }
+
+        let band = spectrum.bands.iter().find(|band| band.nominal == 1000.0);
+        let level = band.unwrap().level_db;
+        assert!((level + 3.01).abs() < 0.05, "{} dB", level);
+    }
+}
@@ -0,0 +1,392 @@
+use std::time::Duration;
+
+use anyhow::Error;
+
+use crate::consumer::FrameAccumulator;
+use crate::fft;
+use crate::spectrum::Window;
+use crate::{SampleConsumer, Samples};
+
+// Length of an analysis frame, rounded up to a power of two samples for the FFT.
+const FRAME_DURATION: f64 = 0.02;
+
+// Band in which spectral flatness is measured, covering most of the speech energy.
+const FLATNESS_BAND: (f64, f64) = (300.0, 4000.0);
+
+/// Change of state reported by the `VoiceActivityDetector`. Times are on the timeline
+/// of the buffer timestamps.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VoiceEvent {
+    /// Speech started at the given time.
+    SpeechStart(gst::ClockTime),
+    /// Speech ended at the given time, the hangover not included.
+    SpeechEnd(gst::ClockTime),
+}
+
+/// Thresholds of the `VoiceActivityDetector`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VadSettings {
+    /// Frames quieter than this mean square level in dBFS are never speech.
+    pub energy_threshold_db: f64,
+    /// Zero crossings per sample above which a frame sounds like noise or fricatives.
+    pub max_zero_crossing_rate: f64,
+    /// Spectral flatness, from 0 for a pure tone towards 1 for white noise, above
+    /// which a frame is considered noise. White noise reads from about 0.45 to 0.7 on
+    /// single frames.
+    pub max_flatness: f64,
+    /// How long frames must keep looking like speech before speech starts.
+    pub min_speech: Duration,
+    /// How long speech is held after the last speech frame, bridging short pauses.
+    pub hangover: Duration,
+}
+
+impl Default for VadSettings {
+    fn default() -> Self {
+        VadSettings {
+            energy_threshold_db: -45.0,
+            max_zero_crossing_rate: 0.25,
+            max_flatness: 0.35,
+            min_speech: Duration::from_millis(60),
+            hangover: Duration::from_millis(300),
+        }
+    }
+}
+
+/// Features measured on one analysis frame.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VoiceFeatures {
+    /// Mean square level in dBFS.
+    pub energy_db: f64,
+    /// Zero crossings per sample.
+    pub zero_crossing_rate: f64,
+    /// Ratio of the geometric to the arithmetic mean of the power spectrum.
+    pub flatness: f64,
+}
+
+impl VoiceFeatures {
+    /// A frame is speech when it is loud enough and both its zero crossing rate and
+    /// its spectral flatness are below the noise thresholds, as in voiced sounds.
+    /// Noise is rejected, fricatives included, and so is loud low-frequency noise
+    /// whose few zero crossings alone would pass.
+    pub fn is_speech(&self, settings: &VadSettings) -> bool {
+        self.energy_db > settings.energy_threshold_db
+            && self.zero_crossing_rate < settings.max_zero_crossing_rate
+            && self.flatness < settings.max_flatness
+    }
+}
+
+/// Voice activity detector combining energy, zero crossing rate and spectral flatness
+/// of the channels mixed down to mono.
+#[derive(Debug)]
+pub struct VoiceActivityDetector {
+    settings: VadSettings,
+    frames: FrameAccumulator,
+    size: usize,
+    window: Vec<f64>,
+    speaking: bool,
+    // Start of the current run of speech frames while not speaking.
+    run_start: Option<u64>,
+    // End of the last speech frame.
+    last_speech_end: u64,
+}
+
+impl Default for VoiceActivityDetector {
+    fn default() -> Self {
+        VoiceActivityDetector::new(VadSettings::default())
+    }
+}
+
+impl VoiceActivityDetector {
+    pub fn new(settings: VadSettings) -> Self {
+        VoiceActivityDetector {
+            settings,
+            frames: FrameAccumulator::default(),
+            size: 0,
+            window: Vec::new(),
+            speaking: false,
+            run_start: None,
+            last_speech_end: 0,
+        }
+    }
+
+    /// Whether speech is currently detected.
+    pub fn is_speaking(&self) -> bool {
+        self.speaking
+    }
+
+    /// Add interleaved `samples` with `channels` channels at `rate` Hz starting at
+    /// timestamp `pts`, and return the events of every frame completed by them.
+    pub fn process(
+        &mut self,
+        samples: &[f32],
+        channels: usize,
+        rate: u32,
+        pts: gst::ClockTime,
+    ) -> Vec<VoiceEvent> {
+        let previous = self.frames.position();
+        if self.frames.start_buffer(rate, pts) {
+            self.size = ((f64::from(rate) * FRAME_DURATION) as usize)
+                .max(2)
+                .next_power_of_two();
+            self.window = Window::Hann.coefficients(self.size);
+        }
+        let position = self.frames.position();
+        if position < previous {
+            // The timestamps jumped back: restart the run of speech frames and hold
+            // speech from the new position.
+            self.run_start = None;
+            self.last_speech_end = self.last_speech_end.min(position);
+        }
+        self.frames.push(samples, channels);
+
+        let mut events = Vec::new();
+        while let Some(frame) = self.frames.block(self.size) {
+            let features = self.features(frame);
+            let start = self.frames.position();
+            let end = self.frames.time(self.size);
+            if let Some(event) = self.update(features.is_speech(&self.settings), start, end) {
+                events.push(event);
+            }
+            self.frames.advance(self.size);
+        }
+        events
+    }
+
+    /// Event ending the speech still going on at the end of the stream, if any.
+    pub fn finish(&mut self) -> Option<VoiceEvent> {
+        self.run_start = None;
+        if !self.speaking {
+            return None;
+        }
+        self.speaking = false;
+        Some(VoiceEvent::SpeechEnd(gst::ClockTime::from_nseconds(
+            self.last_speech_end,
+        )))
+    }
+
+    // Report `events` as metrics.
+    fn report(samples: &Samples, events: impl IntoIterator<Item = VoiceEvent>) {
+        for event in events {
+            let (time, speech) = match event {
+                VoiceEvent::SpeechStart(time) => (time, 1.0),
+                VoiceEvent::SpeechEnd(time) => (time, 0.0),
+            };
+            samples.report(
+                samples
+                    .metric("vad")
+                    .with_timestamp(time)
+                    .with_value("speech", speech),
+            );
+        }
+    }
+
+    // Measure the features of a frame of mono samples at the current rate.
+    fn features(&self, frame: &[f64]) -> VoiceFeatures {
+        let energy = frame.iter().map(|x| x * x).sum::<f64>() / frame.len() as f64;
+
+        let crossings = frame
+            .windows(2)
+            .filter(|pair| (pair[0] >= 0.0) != (pair[1] >= 0.0))
+            .count();
+
+        let power = fft::power_spectrum(frame, &self.window);
+        let bin_width = f64::from(self.frames.rate()) / self.size as f64;
+        let band: Vec<f64> = power
+            .iter()
+            .enumerate()
+            .filter(|(bin, _)| {
+                let frequency = *bin as f64 * bin_width;
+                frequency >= FLATNESS_BAND.0 && frequency < FLATNESS_BAND.1
+            })
+            // Keep digital silence from taking the logarithm of zero.
+            .map(|(_, p)| p + 1e-20)
+            .collect();
+        let flatness = if band.is_empty() {
+            1.0
+        } else {
+            let log_mean = band.iter().map(|p| p.ln()).sum::<f64>() / band.len() as f64;
+            let mean = band.iter().sum::<f64>() / band.len() as f64;
+            log_mean.exp() / mean
+        };
+
+        VoiceFeatures {
+            energy_db: 10.0 * energy.log10(),
+            zero_crossing_rate: crossings as f64 / frame.len() as f64,
+            flatness,
+        }
+    }
+
+    // Advance the state machine by one frame spanning `start` to `end` nanoseconds.
+    fn update(&mut self, speech: bool, start: u64, end: u64) -> Option<VoiceEvent> {
+        let min_speech = self.settings.min_speech.as_nanos() as u64;
+        let hangover = self.settings.hangover.as_nanos() as u64;
+
+        if speech {
+            self.last_speech_end = end;
+            if self.speaking {
+                return None;
+            }
+            let run_start = *self.run_start.get_or_insert(start);
+            if end.saturating_sub(run_start) >= min_speech {
+                self.speaking = true;
+                self.run_start = None;
+                return Some(VoiceEvent::SpeechStart(gst::ClockTime::from_nseconds(
+                    run_start,
+                )));
+            }
+        } else if self.speaking {
+            if end.saturating_sub(self.last_speech_end) >= hangover {
+                self.speaking = false;
+                return Some(VoiceEvent::SpeechEnd(gst::ClockTime::from_nseconds(
+                    self.last_speech_end,
+                )));
+            }
+        } else {
+            self.run_start = None;
+        }
+        None
+    }
+}
+
+impl SampleConsumer for VoiceActivityDetector {
+    fn consume(&mut self, samples: &Samples) -> Result<(), Error> {
+        let events = self.process(
+            samples.data,
+            samples.channels(),
+            samples.info.rate(),
+            samples.pts,
+        );
+        VoiceActivityDetector::report(samples, events);
+        Ok(())
+    }
+
+    fn end_of_stream(&mut self, samples: &Samples) -> Result<(), Error> {
+        VoiceActivityDetector::report(samples, self.finish());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consumer::white_noise;
+    use std::f64::consts::PI;
+
+    // Analysis frames are 512 samples, 32 ms, at this rate.
+    const RATE: u32 = 16_000;
+
+    // Number of samples in `ms`.
+    fn length(ms: u64) -> usize {
+        (ms * u64::from(RATE) / 1000) as usize
+    }
+
+    // `ms` of a voiced sound: a 150 Hz fundamental with falling harmonics.
+    fn voiced(ms: u64) -> Vec<f32> {
+        (0..length(ms))
+            .map(|n| {
+                let t = n as f64 / f64::from(RATE);
+                (1..=10)
+                    .map(|harmonic| {
+                        let harmonic = f64::from(harmonic);
+                        0.3 / harmonic * (2.0 * PI * 150.0 * harmonic * t).sin()
+                    })
+                    .sum::<f64>() as f32
+            })
+            .collect()
+    }
+
+    fn silence(ms: u64) -> Vec<f32> {
+        vec![0.0; length(ms)]
+    }
+
+    // `ms` of 50 Hz mains hum over white noise.
+    fn hum(ms: u64) -> Vec<f32> {
+        white_noise(length(ms))
+            .into_iter()
+            .enumerate()
+            .map(|(n, noise)| {
+                let t = n as f64 / f64::from(RATE);
+                (0.5 * (2.0 * PI * 50.0 * t).sin()) as f32 + 0.2 * noise
+            })
+            .collect()
+    }
+
+    fn start(ms: u64) -> VoiceEvent {
+        VoiceEvent::SpeechStart(gst::ClockTime::from_mseconds(ms))
+    }
+
+    fn end(ms: u64) -> VoiceEvent {
+        VoiceEvent::SpeechEnd(gst::ClockTime::from_mseconds(ms))
+    }
+
+    // Events of `samples` fed in 20 ms buffers timestamped from `start_ms`.
+    fn feed(
+        detector: &mut VoiceActivityDetector,
+        samples: &[f32],
+        start_ms: u64,
+    ) -> Vec<VoiceEvent> {
+        samples
+            .chunks(320)
+            .enumerate()
+            .flat_map(|(index, buffer)| {
+                let pts = gst::ClockTime::from_mseconds(start_ms + 20 * index as u64);
+                detector.process(buffer, 1, RATE, pts)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn voiced_sound_is_speech() {
+        let mut detector = VoiceActivityDetector::default();
+        assert_eq!(feed(&mut detector, &voiced(320), 0), [start(0)]);
+        assert!(detector.is_speaking());
+
+        // White noise crosses zero too often. Hum keeps the crossings of the noise over
+        // it down, but leaves its spectrum flat.
+        for noise in &[white_noise(length(1000)), hum(1000)] {
+            let mut detector = VoiceActivityDetector::default();
+            assert_eq!(feed(&mut detector, noise, 0), []);
+        }
+    }
+
+    #[test]
+    fn speech_start_and_end() {
+        // One frame of speech is shorter than the 60 ms minimum, two are not.
+        let mut detector = VoiceActivityDetector::default();
+        let mut samples = silence(320);
+        samples.extend(voiced(32));
+        samples.extend(silence(320));
+        assert_eq!(feed(&mut detector, &samples, 0), []);
+
+        // A 160 ms pause is bridged by the 300 ms hangover, the final silence is not.
+        let mut detector = VoiceActivityDetector::default();
+        let mut samples = silence(320);
+        samples.extend(voiced(64));
+        samples.extend(silence(160));
+        samples.extend(voiced(192));
+        samples.extend(silence(640));
+        assert_eq!(feed(&mut detector, &samples, 0), [start(320), end(736)]);
+    }
+
+    #[test]
+    fn timestamps_going_back() {
+        // A frame of speech at 10 s does not count towards speech after a jump back.
+        let mut detector = VoiceActivityDetector::default();
+        assert_eq!(feed(&mut detector, &voiced(32), 10_000), []);
+        assert_eq!(feed(&mut detector, &voiced(192), 1000), [start(1000)]);
+
+        // Speech held from 10 s ends a hangover after the jump back.
+        let mut detector = VoiceActivityDetector::default();
+        assert_eq!(feed(&mut detector, &voiced(320), 10_000), [start(10_000)]);
+        assert_eq!(feed(&mut detector, &silence(640), 1000), [end(1000)]);
+    }
+
+    #[test]
+    fn speech_ended_by_end_of_stream() {
+        let mut detector = VoiceActivityDetector::default();
+        assert_eq!(feed(&mut detector, &voiced(320), 0), [start(0)]);
+        assert_eq!(detector.finish(), Some(end(320)));
+        assert!(!detector.is_speaking());
+        assert_eq!(detector.finish(), None);
+    }
+}